@@ -0,0 +1,195 @@
+//! Micro benchmarks for the pieces a search spends its time in, each run
+//! over every move of every board in `corpus.txt` so a regression in one of
+//! them shows up on its own rather than only in the whole search.
+
+use bilge::bench::{parse_corpus, DEFAULT_CORPUS};
+use bilge::board::defs::{BOARD_SIZE, WIDTH};
+use bilge::board::searcher::{self, SearchPool};
+use bilge::board::GameState;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+const SEARCH_DEPTH: u8 = 4;
+
+fn corpus() -> Vec<GameState> {
+    parse_corpus(DEFAULT_CORPUS)
+        .unwrap()
+        .iter()
+        .map(|entry| entry.game())
+        .collect()
+}
+
+/// Every board with each of its moves, the pair already swapped over
+fn swapped(games: &[GameState]) -> Vec<(GameState, usize)> {
+    games
+        .iter()
+        .flat_map(|game| {
+            game.get_moves().into_iter().map(move |pos| {
+                let mut copy = *game;
+                copy.board.swap(pos, pos + 1);
+                (copy, pos)
+            })
+        })
+        .collect()
+}
+
+/// Swapped boards which made a line, with the line marked to clear
+fn breaking(games: &[GameState]) -> Vec<(GameState, usize)> {
+    swapped(games)
+        .into_iter()
+        .filter(|(game, pos)| game.get_combo(*pos).0 > 0)
+        .map(|(mut game, pos)| {
+            game.mark_clears();
+            (game, pos)
+        })
+        .collect()
+}
+
+fn moves(c: &mut Criterion) {
+    let games = corpus();
+
+    c.bench_function("get_moves", |b| {
+        b.iter(|| {
+            for game in games.iter() {
+                black_box(black_box(game).get_moves());
+            }
+        })
+    });
+
+    c.bench_function("swap", |b| {
+        b.iter(|| {
+            for game in games.iter() {
+                for pos in game.get_moves() {
+                    let mut copy = *black_box(game);
+                    let _ = black_box(copy.swap(pos));
+                }
+            }
+        })
+    });
+
+    let pairs = swapped(&games);
+
+    c.bench_function("get_combo", |b| {
+        b.iter(|| {
+            for (game, pos) in pairs.iter() {
+                black_box(black_box(game).get_combo(*pos));
+            }
+        })
+    });
+}
+
+fn clearing(c: &mut Criterion) {
+    let games = corpus();
+    let pairs = swapped(&games);
+    let marked = breaking(&games);
+
+    c.bench_function("mark_clears", |b| {
+        b.iter(|| {
+            for (game, _) in pairs.iter() {
+                let mut copy = *black_box(game);
+                black_box(copy.mark_clears());
+            }
+        })
+    });
+
+    c.bench_function("mark_clears_targetted", |b| {
+        b.iter(|| {
+            for (game, pos) in pairs.iter() {
+                let mut copy = *black_box(game);
+                let mut removing_tracker = [0; BOARD_SIZE];
+                let mut removing_count = 2;
+
+                removing_tracker[0] = *pos;
+                removing_tracker[1] = pos + 1;
+
+                black_box(copy.mark_clears_targetted(&mut removing_count, &mut removing_tracker));
+            }
+        })
+    });
+
+    c.bench_function("jelly", |b| {
+        b.iter(|| {
+            for game in games.iter() {
+                for piece in 0..7 {
+                    let mut copy = *black_box(game);
+                    copy.jelly(piece);
+                    black_box(copy.clear_count());
+                }
+            }
+        })
+    });
+
+    c.bench_function("cascade_score", |b| {
+        b.iter(|| {
+            for (game, _) in marked.iter() {
+                black_box(black_box(game).cascade_score());
+            }
+        })
+    });
+
+    let cleared: Vec<GameState> = marked
+        .iter()
+        .map(|(game, _)| {
+            let mut copy = *game;
+            copy.remove_clears();
+            copy
+        })
+        .collect();
+
+    c.bench_function("shift_everything", |b| {
+        b.iter(|| {
+            for game in cleared.iter() {
+                let mut copy = *black_box(game);
+                copy.shift_everything();
+                black_box(copy.board);
+            }
+        })
+    });
+
+    c.bench_function("simple_tracker", |b| {
+        b.iter(|| {
+            for (game, _) in marked.iter() {
+                let mut copy = *black_box(game);
+                let mut position_tracker = [-1; WIDTH];
+                let mut removing_tracker = [0; BOARD_SIZE];
+                let mut removing_count = 0;
+
+                copy.remove_clears_max(&mut position_tracker);
+                copy.simple_tracker(&mut position_tracker, &mut removing_count, &mut removing_tracker);
+                black_box(removing_count);
+            }
+        })
+    });
+}
+
+fn search(c: &mut Criterion) {
+    let games = corpus();
+    let pool = SearchPool::default();
+
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+
+    // A fresh table every run, otherwise every run after the first is all hits
+    group.bench_function(format!("depth {} corpus", SEARCH_DEPTH), |b| {
+        b.iter_batched(
+            || searcher::new_hash_table(1 << 20),
+            |table| {
+                for game in games.iter() {
+                    black_box(searcher::find_best_move_list_in(
+                        game,
+                        SEARCH_DEPTH,
+                        false,
+                        &table,
+                        &pool,
+                    ));
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, moves, clearing, search);
+criterion_main!(benches);
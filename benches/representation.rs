@@ -0,0 +1,106 @@
+//! Byte array against bitboard, the same plain search over both so the
+//! only difference is how the board is stored. The search here is every
+//! move to a fixed depth with no table, pruning or tide, not the engine's,
+//! so the numbers compare the layouts and not what the searcher would gain.
+
+use bilge::board::bitboard::BitBoard;
+use bilge::board::{board_from_str, generate_settled_board, GameState};
+use bilge::config::TEST_BOARD;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const DEPTH: u8 = 3;
+const DROP_PER_TURN: f32 = 0.9;
+
+fn search_bytes(game: &GameState, depth: u8) -> f32 {
+    game.get_moves()
+        .iter()
+        .map(|pos| {
+            let mut copy = *game;
+            let score = copy.swap(*pos).unwrap().score;
+
+            if depth == 1 {
+                score
+            } else {
+                score + search_bytes(&copy, depth - 1) * DROP_PER_TURN
+            }
+        })
+        .fold(0.0, f32::max)
+}
+
+fn search_bits(board: &BitBoard, depth: u8) -> f32 {
+    board
+        .get_moves()
+        .iter()
+        .map(|pos| {
+            let mut copy = *board;
+            let score = copy.swap(*pos).unwrap().score;
+
+            if depth == 1 {
+                score
+            } else {
+                score + search_bits(&copy, depth - 1) * DROP_PER_TURN
+            }
+        })
+        .fold(0.0, f32::max)
+}
+
+fn corpus() -> Vec<GameState> {
+    let mut rng = StdRng::seed_from_u64(0xb17);
+    let mut boards = vec![board_from_str(TEST_BOARD, 3)];
+
+    boards.extend((0..3).map(|_| generate_settled_board(&mut rng, 3)));
+    boards
+}
+
+fn representation(c: &mut Criterion) {
+    let games = corpus();
+    let bits: Vec<BitBoard> = games.iter().map(BitBoard::from_state).collect();
+
+    for (game, board) in games.iter().zip(bits.iter()) {
+        assert_eq!(search_bytes(game, DEPTH), search_bits(board, DEPTH));
+    }
+
+    let mut group = c.benchmark_group("search depth 3");
+
+    group.bench_function("byte array", |b| {
+        b.iter(|| games.iter().map(|game| search_bytes(black_box(game), DEPTH)).sum::<f32>())
+    });
+
+    group.bench_function("bitboard", |b| {
+        b.iter(|| bits.iter().map(|board| search_bits(black_box(board), DEPTH)).sum::<f32>())
+    });
+
+    group.finish();
+
+    let mut group = c.benchmark_group("swap every move");
+
+    group.bench_function("byte array", |b| {
+        b.iter(|| {
+            for game in games.iter() {
+                for pos in game.get_moves() {
+                    let mut copy = *black_box(game);
+                    let _ = black_box(copy.swap(pos));
+                }
+            }
+        })
+    });
+
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            for board in bits.iter() {
+                for pos in board.get_moves() {
+                    let mut copy = *black_box(board);
+                    let _ = black_box(copy.swap(pos));
+                }
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, representation);
+criterion_main!(benches);
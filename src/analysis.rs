@@ -0,0 +1,316 @@
+//! Where a recorded game lost points. Every position is searched again and
+//! the move played is placed among the moves the search ranked, with how
+//! far it fell behind the best one and what the best one would have made.
+//! The summary adds up the combos missed along the way, biggest loss first.
+
+use crate::board::scoring::ComboKind;
+use crate::board::searcher::{self, HashTable, SearchPool, TurnList};
+use crate::record::{GameRecord, Position, MISTAKE_MARGIN};
+
+use serde::Serialize;
+
+/// Worst turns the summary lists
+const WORST_SHOWN: usize = 5;
+
+/// One recorded move against the search of its position
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MoveReview {
+    pub number: usize,
+    #[serde(rename = "move")]
+    pub turn: usize,
+    /// What the move really scored in the game
+    pub scored: f32,
+    pub combo: ComboKind,
+    /// 1 plus the number of moves scoring more, so moves tied with the
+    /// best are all 1
+    pub rank: usize,
+    pub moves: usize,
+    pub score: f32,
+    pub best_move: usize,
+    pub best_score: f32,
+    pub best_combo: ComboKind,
+    /// Points behind the best move by the search's count
+    pub lost: f32,
+}
+
+impl MoveReview {
+    pub fn is_mistake(&self) -> bool {
+        self.lost > MISTAKE_MARGIN
+    }
+
+    /// The best move made a combo this one didn't
+    pub fn missed_combo(&self) -> bool {
+        self.is_mistake() && self.best_combo != ComboKind::Nothing && self.best_combo != self.combo
+    }
+}
+
+/// Compares the move played at `position` with the search of it, `None`
+/// when the search has no moves or doesn't list the one played
+pub fn review(position: &Position, list: &TurnList) -> Option<MoveReview> {
+    let best = list.turns.first()?;
+    let played = list.turns.iter().find(|info| info.turn == position.turn.turn)?;
+    let better = list.turns.iter().filter(|info| info.score > played.score).count();
+
+    Some(MoveReview {
+        number: position.number,
+        turn: played.turn,
+        scored: position.turn.score,
+        combo: played.combo,
+        rank: better + 1,
+        moves: list.turns.len(),
+        score: played.score,
+        best_move: best.turn,
+        best_score: best.score,
+        best_combo: best.combo,
+        lost: best.score - played.score,
+    })
+}
+
+/// Searches every position of the record at `depth`
+pub fn analyze_record(
+    record: &GameRecord,
+    depth: u8,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> Result<Vec<MoveReview>, String> {
+    let positions = record.positions()?;
+    let mut reviews = Vec::with_capacity(positions.len());
+
+    for position in positions.iter() {
+        let list =
+            searcher::find_best_move_list_in(&position.game, depth, false, hash_table, pool);
+
+        let reviewed = review(position, &list).ok_or(format!(
+            "Turn {}: the search doesn't list move {}",
+            position.number, position.turn.turn
+        ))?;
+
+        reviews.push(reviewed);
+    }
+
+    Ok(reviews)
+}
+
+/// Every time one kind of combo was passed over
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MissedCombo {
+    pub combo: ComboKind,
+    pub count: usize,
+    pub lost: f32,
+    /// Turn numbers, counting from 1
+    pub turns: Vec<usize>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub turns: usize,
+    pub mistakes: usize,
+    pub lost: f32,
+    pub average_rank: f32,
+    /// Biggest loss first
+    pub missed: Vec<MissedCombo>,
+    /// Turn numbers of the costliest mistakes, worst first
+    pub worst: Vec<usize>,
+}
+
+pub fn summarize(reviews: &[MoveReview]) -> Summary {
+    let mistakes: Vec<&MoveReview> = reviews.iter().filter(|review| review.is_mistake()).collect();
+
+    let mut missed: Vec<MissedCombo> = Vec::new();
+
+    for review in reviews.iter().filter(|review| review.missed_combo()) {
+        match missed.iter_mut().find(|missed| missed.combo == review.best_combo) {
+            Some(missed) => {
+                missed.count += 1;
+                missed.lost += review.lost;
+                missed.turns.push(review.number);
+            }
+            None => missed.push(MissedCombo {
+                combo: review.best_combo,
+                count: 1,
+                lost: review.lost,
+                turns: vec![review.number],
+            }),
+        }
+    }
+
+    missed.sort_by(|a, b| b.lost.total_cmp(&a.lost));
+
+    let mut worst = mistakes.clone();
+    worst.sort_by(|a, b| b.lost.total_cmp(&a.lost));
+
+    let average_rank = if reviews.is_empty() {
+        0.0
+    } else {
+        reviews.iter().map(|review| review.rank as f32).sum::<f32>() / reviews.len() as f32
+    };
+
+    Summary {
+        turns: reviews.len(),
+        mistakes: mistakes.len(),
+        lost: mistakes.iter().map(|review| review.lost).sum(),
+        average_rank,
+        missed,
+        worst: worst
+            .iter()
+            .take(WORST_SHOWN)
+            .map(|review| review.number)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::book::Book;
+    use crate::board::searcher::{new_hash_table, Info};
+    use crate::board::{board_from_str, fill_cleared};
+    use crate::config::TEST_BOARD;
+    use crate::record::{gaps, RecordedTurn};
+    use std::sync::Arc;
+
+    fn info(turn: usize, score: f32, combo: ComboKind) -> Info {
+        Info { turn, score, combo }
+    }
+
+    fn list(turns: Vec<Info>) -> TurnList {
+        TurnList {
+            turns,
+            positions: 0,
+            hash_hits: 0,
+            info_str: String::new(),
+        }
+    }
+
+    fn reviewed(number: usize, turns: Vec<Info>, played: usize) -> MoveReview {
+        let turn = RecordedTurn {
+            turn: played,
+            score: 0.0,
+            refill: Vec::new(),
+            suggested: None,
+            suggested_score: None,
+        };
+        let position = Position {
+            number,
+            game: board_from_str(TEST_BOARD, 3),
+            turn: &turn,
+        };
+
+        review(&position, &list(turns)).unwrap()
+    }
+
+    #[test]
+    fn test_review() {
+        let turns = vec![
+            info(23, 60.0, ComboKind::Jellyfish),
+            info(12, 30.5, ComboKind::Bingo),
+            info(40, 10.0, ComboKind::Single),
+        ];
+
+        let best = reviewed(1, turns.clone(), 23);
+        assert_eq!(best.rank, 1);
+        assert_eq!(best.lost, 0.0);
+        assert!(!best.is_mistake());
+
+        let third = reviewed(2, turns.clone(), 40);
+        assert_eq!((third.rank, third.moves), (3, 3));
+        assert_eq!(third.lost, 50.0);
+        assert_eq!(third.best_move, 23);
+        assert!(third.missed_combo());
+
+        // Tied with the best is as good as the best
+        let mut tied = turns.clone();
+        tied.insert(1, info(7, 60.0, ComboKind::Single));
+        let level = reviewed(4, tied.clone(), 7);
+        assert_eq!((level.rank, level.lost), (1, 0.0));
+        assert!(!level.missed_combo());
+        assert_eq!(reviewed(5, tied, 40).rank, 4);
+
+        let second = reviewed(3, turns.clone(), 12);
+        let summary = summarize(&[best, third, second]);
+
+        assert_eq!(summary.turns, 3);
+        assert_eq!(summary.mistakes, 2);
+        assert_eq!(summary.lost, 79.5);
+        assert_eq!(summary.average_rank, 2.0);
+        assert_eq!(summary.worst, vec![2, 3]);
+        assert_eq!(
+            summary.missed,
+            vec![MissedCombo {
+                combo: ComboKind::Jellyfish,
+                count: 2,
+                lost: 79.5,
+                turns: vec![2, 3],
+            }]
+        );
+
+        let turn = RecordedTurn {
+            turn: 5,
+            score: 0.0,
+            refill: Vec::new(),
+            suggested: None,
+            suggested_score: None,
+        };
+        let position = Position {
+            number: 1,
+            game: board_from_str(TEST_BOARD, 3),
+            turn: &turn,
+        };
+        assert!(review(&position, &list(turns)).is_none());
+    }
+
+    #[test]
+    fn test_analyze_record() {
+        let mut game = board_from_str(TEST_BOARD, 3);
+        let mut record = GameRecord::new(&game).unwrap();
+
+        // Always the lowest legal move, rarely the best
+        for _ in 0..3 {
+            let turn = game.get_moves()[0];
+            let score = game.swap(turn).unwrap().score;
+            let gaps = gaps(&game);
+            fill_cleared(&mut game);
+
+            record.turns.push(RecordedTurn {
+                turn,
+                score,
+                refill: gaps.iter().map(|pos| game.board[*pos]).collect(),
+                suggested: None,
+                suggested_score: None,
+            });
+        }
+
+        let hash_table = new_hash_table(1000);
+        let reviews = analyze_record(&record, 2, &hash_table, &SearchPool::default()).unwrap();
+
+        assert_eq!(reviews.len(), 3);
+
+        for (review, turn) in reviews.iter().zip(record.turns.iter()) {
+            assert_eq!(review.turn, turn.turn);
+            assert!(review.moves > 1);
+            assert!(review.rank >= 1 && review.rank <= review.moves);
+            assert!(review.lost >= 0.0);
+            assert_eq!(review.rank == 1, review.lost == 0.0);
+        }
+
+        // A book ranking every position is read like the search, every
+        // move included
+        let mut book = Book::new();
+        for position in record.positions().unwrap().iter() {
+            let mut ranked =
+                searcher::find_best_move_list_in(&position.game, 2, false, &hash_table, &SearchPool::default());
+            ranked.turns.iter_mut().for_each(|info| info.score -= 1000.0);
+            book.insert(&position.game, &ranked, 8);
+        }
+
+        let mut pool = SearchPool::default();
+        pool.book = Some(Arc::new(book));
+
+        let from_book = analyze_record(&record, 2, &hash_table, &pool).unwrap();
+
+        for (review, searched) in from_book.iter().zip(reviews.iter()) {
+            assert_eq!((review.rank, review.moves), (searched.rank, searched.moves));
+            assert_eq!(review.score, searched.score - 1000.0);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::board::searcher::{self, HashTable, SearchPool};
+use crate::board::{board_from_str, GameState};
+use crate::cli::validate_board;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The boards `cargo bench` uses, so both benchmarks time the same thing
+pub const DEFAULT_CORPUS: &str = include_str!("../benches/corpus.txt");
+
+/// Bumped whenever a baseline saved by an older build can't be compared
+pub const BASELINE_VERSION: u32 = 1;
+
+/// Slowdowns smaller than this are noise whatever the threshold says, the
+/// quick boards would fail on a busy machine otherwise
+const NOISE_MS: f64 = 2.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorpusBoard {
+    pub board: String,
+    pub water: u8,
+}
+
+impl CorpusBoard {
+    pub fn game(&self) -> GameState {
+        board_from_str(&self.board, self.water)
+    }
+}
+
+/// Reads a corpus, one `<board> [water]` per line with `#` comments. The
+/// water defaults to 3 like everywhere else.
+pub fn parse_corpus(input: &str) -> Result<Vec<CorpusBoard>, String> {
+    let mut boards = Vec::new();
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let board = parts.next().unwrap_or_default();
+
+        validate_board(board).map_err(|err| format!("Line {}: {}", number + 1, err))?;
+
+        let water = match parts.next() {
+            Some(water) => water
+                .parse()
+                .map_err(|_| format!("Line {}: water should be a number, got '{}'", number + 1, water))?,
+            None => 3,
+        };
+
+        if let Some(extra) = parts.next() {
+            return Err(format!("Line {}: unexpected '{}'", number + 1, extra));
+        }
+
+        boards.push(CorpusBoard {
+            board: board.to_string(),
+            water,
+        });
+    }
+
+    if boards.is_empty() {
+        return Err("The corpus has no boards".to_string());
+    }
+
+    Ok(boards)
+}
+
+/// One board searched at one depth, the time is the fastest of the runs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub board: String,
+    pub water: u8,
+    pub depth: u8,
+    #[serde(rename = "move")]
+    pub turn: Option<usize>,
+    pub score: f32,
+    pub positions: usize,
+    pub elapsed_ms: f64,
+}
+
+impl BenchResult {
+    pub fn nodes_per_sec(&self) -> f64 {
+        if self.elapsed_ms > 0.0 {
+            self.positions as f64 / (self.elapsed_ms / 1000.0)
+        } else {
+            0.0
+        }
+    }
+
+    fn same_position(&self, other: &BenchResult) -> bool {
+        self.board == other.board && self.water == other.water && self.depth == other.depth
+    }
+}
+
+/// Searches a board `runs` times with an empty table each time
+pub fn run_board(
+    entry: &CorpusBoard,
+    depth: u8,
+    runs: usize,
+    map: &HashTable,
+    pool: &SearchPool,
+) -> BenchResult {
+    let game = entry.game();
+    let mut fastest = Duration::MAX;
+    let mut result = None;
+
+    for _ in 0..runs.max(1) {
+        let now = Instant::now();
+        let best_moves = searcher::find_best_move_list_in(&game, depth, false, map, pool);
+        let time_taken = now.elapsed();
+
+        // Keeps the table's memory rather than allocating it all again
+        map.clear();
+
+        fastest = fastest.min(time_taken);
+        result = Some(best_moves);
+    }
+
+    let best_moves = result.unwrap();
+    let best = best_moves.turns.first();
+
+    BenchResult {
+        board: entry.board.clone(),
+        water: entry.water,
+        depth,
+        turn: best.map(|info| info.turn),
+        score: best.map_or(0.0, |info| info.score),
+        positions: best_moves.positions,
+        elapsed_ms: fastest.as_secs_f64() * 1000.0,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Baseline {
+    pub version: u32,
+    pub results: Vec<BenchResult>,
+}
+
+impl Baseline {
+    pub fn new(results: Vec<BenchResult>) -> Baseline {
+        Baseline {
+            version: BASELINE_VERSION,
+            results,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(input: &str) -> Result<Baseline, String> {
+        let baseline: Baseline =
+            serde_json::from_str(input).map_err(|err| format!("Bad baseline: {}", err))?;
+
+        if baseline.version != BASELINE_VERSION {
+            return Err(format!(
+                "Baseline is version {}, this build writes version {}, save a new one",
+                baseline.version, BASELINE_VERSION
+            ));
+        }
+
+        Ok(baseline)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Regression {
+    MoveChanged {
+        board: String,
+        depth: u8,
+        was: Option<usize>,
+        now: Option<usize>,
+    },
+    Slower {
+        board: String,
+        depth: u8,
+        was_ms: f64,
+        now_ms: f64,
+    },
+}
+
+fn move_name(turn: &Option<usize>) -> String {
+    turn.map_or("nothing".to_string(), |turn| turn.to_string())
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Regression::MoveChanged { board, depth, was, now } => write!(
+                f,
+                "{} at depth {} moved {}, the baseline moved {}",
+                board,
+                depth,
+                move_name(now),
+                move_name(was)
+            ),
+            Regression::Slower { board, depth, was_ms, now_ms } => write!(
+                f,
+                "{} at depth {} took {:.1}ms, the baseline took {:.1}ms ({:+.0}%)",
+                board,
+                depth,
+                now_ms,
+                was_ms,
+                (now_ms / was_ms - 1.0) * 100.0
+            ),
+        }
+    }
+}
+
+/// Compares a run against the baseline, results the baseline never saw are
+/// skipped so the corpus can grow without a new baseline. `threshold` is
+/// how much slower in percent a board may get before it counts.
+pub fn compare(baseline: &Baseline, results: &[BenchResult], threshold: f64) -> Vec<Regression> {
+    let mut regressions = Vec::new();
+
+    for result in results {
+        let old = match baseline.results.iter().find(|old| old.same_position(result)) {
+            Some(old) => old,
+            None => continue,
+        };
+
+        if old.turn != result.turn {
+            regressions.push(Regression::MoveChanged {
+                board: result.board.clone(),
+                depth: result.depth,
+                was: old.turn,
+                now: result.turn,
+            });
+        }
+
+        let allowed = old.elapsed_ms * (1.0 + threshold / 100.0);
+
+        if result.elapsed_ms > allowed && result.elapsed_ms - old.elapsed_ms > NOISE_MS {
+            regressions.push(Regression::Slower {
+                board: result.board.clone(),
+                depth: result.depth,
+                was_ms: old.elapsed_ms,
+                now_ms: result.elapsed_ms,
+            });
+        }
+    }
+
+    regressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TEST_BOARD;
+
+    fn result(turn: usize, elapsed_ms: f64) -> BenchResult {
+        BenchResult {
+            board: TEST_BOARD.to_string(),
+            water: 3,
+            depth: 5,
+            turn: Some(turn),
+            score: 12.0,
+            positions: 1000,
+            elapsed_ms,
+        }
+    }
+
+    #[test]
+    fn test_parse_corpus() {
+        let corpus = format!("# comment\n\n{} 5\n{}\n", TEST_BOARD, TEST_BOARD);
+        let boards = parse_corpus(&corpus).unwrap();
+
+        assert_eq!(boards.len(), 2);
+        assert_eq!(boards[0].water, 5);
+        assert_eq!(boards[1].water, 3);
+
+        assert!(parse_corpus("# nothing here").is_err());
+        assert!(parse_corpus("1234 3").is_err());
+        assert!(parse_corpus(&format!("{} deep", TEST_BOARD)).is_err());
+        assert!(parse_corpus(&format!("{} 3 4", TEST_BOARD)).is_err());
+
+        assert!(parse_corpus(DEFAULT_CORPUS).unwrap().len() > 1);
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = Baseline::new(vec![result(39, 100.0)]);
+
+        assert!(compare(&baseline, &[result(39, 105.0)], 10.0).is_empty());
+        assert!(compare(&baseline, &[result(39, 80.0)], 10.0).is_empty());
+
+        assert_eq!(
+            compare(&baseline, &[result(39, 120.0)], 10.0),
+            vec![Regression::Slower {
+                board: TEST_BOARD.to_string(),
+                depth: 5,
+                was_ms: 100.0,
+                now_ms: 120.0
+            }]
+        );
+
+        assert_eq!(
+            compare(&baseline, &[result(40, 100.0)], 10.0),
+            vec![Regression::MoveChanged {
+                board: TEST_BOARD.to_string(),
+                depth: 5,
+                was: Some(39),
+                now: Some(40)
+            }]
+        );
+
+        // Another depth isn't in the baseline so can't regress
+        let mut deeper = result(40, 900.0);
+        deeper.depth = 6;
+        assert!(compare(&baseline, &[deeper], 10.0).is_empty());
+
+        // A tiny board getting a millisecond slower is noise
+        let quick = Baseline::new(vec![result(39, 0.5)]);
+        assert!(compare(&quick, &[result(39, 1.5)], 10.0).is_empty());
+    }
+
+    #[test]
+    fn test_baseline_round_trip() {
+        let baseline = Baseline::new(vec![result(39, 100.0)]);
+        let read = Baseline::from_json(&baseline.to_json()).unwrap();
+
+        assert_eq!(read.results, baseline.results);
+
+        let old = baseline.to_json().replace("\"version\": 1", "\"version\": 0");
+        assert!(Baseline::from_json(&old).is_err());
+        assert!(Baseline::from_json("{").is_err());
+    }
+}
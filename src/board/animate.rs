@@ -0,0 +1,247 @@
+//! Plays a traced swap back in the terminal a frame at a time: the pair
+//! before and after it trades places, then for every round of the cascade
+//! the cells about to clear and the board once the rest has floated up.
+//! Colours follow `draw_highlight`, the swapped pair is green and anything
+//! under water blue, with cells about to clear on yellow and special pieces
+//! going off on magenta.
+
+use crate::board::defs::{self, Pieces, BOARD_SIZE, HEIGHT, LAST_COLUMN, WIDTH};
+use crate::board::helpers::x_pos_fast;
+use crate::board::rules::{self, IllegalMove};
+use crate::board::scoring::ComboKind;
+use crate::board::trace::{SwapOutcome, Triggered};
+use crate::board::GameState;
+
+use recolored::*;
+use std::io::{IsTerminal, Write};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_FRAME_TIME: Duration = Duration::from_millis(700);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mark {
+    Plain,
+    Swapped,
+    Clearing,
+    Special,
+}
+
+/// A board and the line under it saying what is going on
+pub struct Frame {
+    pub text: String,
+    pub caption: String,
+}
+
+impl Frame {
+    fn new(
+        board: &[Pieces; BOARD_SIZE],
+        water_level: u8,
+        caption: String,
+        mark: impl Fn(usize) -> Mark,
+    ) -> Frame {
+        let mut text = String::from("\n");
+
+        for y in (0..HEIGHT).rev() {
+            for x in 0..WIDTH {
+                let pos = (y * WIDTH) + x;
+                let piece = defs::draw_piece(board[pos]);
+
+                let drawn = match mark(pos) {
+                    Mark::Swapped => piece.bright_green(),
+                    Mark::Clearing => piece.black().on_bright_yellow(),
+                    Mark::Special => piece.black().on_magenta(),
+                    Mark::Plain if x_pos_fast(pos) == LAST_COLUMN => piece.red(),
+                    Mark::Plain if rules::underwater(y, water_level) => piece.blue(),
+                    Mark::Plain => piece.normal(),
+                };
+
+                text.push_str(&drawn.to_string());
+            }
+
+            if rules::underwater(y, water_level) {
+                text.push_str(&format!(" : {} {}\n", y, "~".blue()));
+            } else {
+                text.push_str(&format!(" : {}\n", y));
+            }
+        }
+
+        Frame { text, caption }
+    }
+
+    /// Lines the frame takes up once printed, the board, a gap and the caption
+    pub fn height(&self) -> usize {
+        self.text.lines().count() + 2
+    }
+}
+
+fn describe(triggered: &Triggered) -> String {
+    match triggered {
+        Triggered::Pufferfish { pos } => format!("pufferfish at {} goes off", pos),
+        Triggered::Jellyfish { pos, cleared } => format!(
+            "jellyfish at {} takes every {}",
+            pos,
+            defs::draw_piece(*cleared)
+        ),
+        Triggered::Crab { pos } => format!("crab at {} escapes", pos),
+    }
+}
+
+fn special_at(triggered: &[Triggered], pos: usize) -> bool {
+    triggered.iter().any(|special| match special {
+        Triggered::Pufferfish { pos: at }
+        | Triggered::Jellyfish { pos: at, .. }
+        | Triggered::Crab { pos: at } => *at == pos,
+    })
+}
+
+/// Every frame of the swap, starting from `game` as it was before
+pub fn frames(game: &GameState, outcome: &SwapOutcome) -> Vec<Frame> {
+    let water = game.water_level;
+    let pair = |pos: usize| pos == outcome.pos || pos == outcome.pos + 1;
+    let swap_mark = |pos: usize| if pair(pos) { Mark::Swapped } else { Mark::Plain };
+
+    let mut frames = vec![Frame::new(
+        &game.board,
+        water,
+        format!("Swapping {} and {}", outcome.pos, outcome.pos + 1),
+        swap_mark,
+    )];
+
+    frames.push(Frame::new(
+        &outcome.swapped,
+        water,
+        match outcome.combo {
+            ComboKind::Nothing => "Nothing lines up".to_string(),
+            combo => format!("That makes a {}", combo.name()),
+        },
+        swap_mark,
+    ));
+
+    let mut before = &outcome.swapped;
+
+    for (number, step) in outcome.steps.iter().enumerate() {
+        let mask = step.clear_mask();
+        let mut caption = format!(
+            "Step {}: {} cells clear for {}",
+            number + 1,
+            step.cleared().count(),
+            step.score
+        );
+
+        for special in step.triggered.iter() {
+            caption.push_str(", ");
+            caption.push_str(&describe(special));
+        }
+
+        frames.push(Frame::new(before, water, caption, |pos| {
+            if special_at(&step.triggered, pos) {
+                Mark::Special
+            } else if mask & (1 << pos) != 0 {
+                Mark::Clearing
+            } else {
+                Mark::Plain
+            }
+        }));
+
+        frames.push(Frame::new(
+            &step.board,
+            water,
+            format!("Step {}: the rest floats up", number + 1),
+            |_| Mark::Plain,
+        ));
+
+        before = &step.board;
+    }
+
+    frames.push(Frame::new(
+        outcome.board(),
+        water,
+        format!(
+            "Scored {} over {} step{}",
+            outcome.score,
+            outcome.steps.len(),
+            if outcome.steps.len() == 1 { "" } else { "s" }
+        ),
+        |_| Mark::Plain,
+    ));
+
+    frames
+}
+
+/// Prints the frames one after another in the same place, or one under
+/// the other with no pause when the output isn't a terminal
+pub fn play<W: Write>(out: &mut W, frames: &[Frame], frame_time: Duration, in_place: bool) {
+    let mut last_height = 0;
+
+    for frame in frames {
+        if in_place && last_height > 0 {
+            // Back up over the last frame and clear it
+            let _ = write!(out, "\x1b[{}A\x1b[J", last_height);
+        }
+
+        let _ = write!(out, "{}\n{}\n", frame.text, frame.caption);
+        let _ = out.flush();
+
+        last_height = frame.height();
+
+        if in_place {
+            thread::sleep(frame_time);
+        }
+    }
+}
+
+impl GameState {
+    /// Animates the swap at `pos` on stdout, leaving the board as it was
+    pub fn animate_swap(&self, pos: usize, frame_time: Duration) -> Result<(), IllegalMove> {
+        let mut copy = *self;
+        let outcome = copy.swap_traced(pos)?;
+
+        let stdout = std::io::stdout();
+        let in_place = stdout.is_terminal();
+        play(&mut stdout.lock(), &frames(self, &outcome), frame_time, in_place);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_from_str;
+    use crate::config::TEST_BOARD;
+
+    #[test]
+    fn test_frames() {
+        let game = board_from_str(TEST_BOARD, 3);
+
+        for pos in game.get_moves() {
+            let mut copy = game;
+            let outcome = copy.swap_traced(pos).unwrap();
+            let frames = frames(&game, &outcome);
+
+            // Before, after the swap, two for every step and the total
+            assert_eq!(frames.len(), 3 + 2 * outcome.steps.len());
+            assert!(frames.iter().all(|frame| frame.height() == 15));
+
+            let last = &frames[frames.len() - 1];
+            assert!(last.caption.starts_with(&format!("Scored {}", outcome.score)));
+        }
+    }
+
+    #[test]
+    fn test_play_without_terminal() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let mut copy = game;
+        let outcome = copy.swap_traced(39).unwrap();
+        let frames = frames(&game, &outcome);
+
+        let mut out = Vec::new();
+        play(&mut out, &frames, Duration::from_secs(10), false);
+
+        let printed = String::from_utf8(out).unwrap();
+        assert!(!printed.contains("\x1b[J"));
+        assert_eq!(printed.lines().count(), frames.iter().map(Frame::height).sum::<usize>());
+        assert!(printed.contains("Swapping 39 and 40"));
+    }
+}
@@ -0,0 +1,529 @@
+//! Alternative board made of one bitmask per piece type instead of a byte
+//! per cell. Lines come out of a few shifts and ANDs per piece rather than a
+//! scan of every cell, and gravity compacts each column with PEXT/PDEP.
+//!
+//! Cells are laid out a column at a time, bit `x * 16 + y`, so every column
+//! is its own 16 bit lane with the bits above `HEIGHT` always empty. Shifting by
+//! one moves along a column and by 16 along a row, and neither can wrap into
+//! the next column or row as long as the spare bits stay clear.
+//!
+//! It plays exactly like `GameState`, which the tests check move for move.
+//! `NULL` cells can't be stored and come back as `CLEARED`. There is no
+//! `Tide`, the water level only changes when set, so it matches a game
+//! whose water stays put and nothing else.
+
+use crate::board::defs::*;
+use crate::board::rules::{self, IllegalMove};
+use crate::board::scoring::{self, ComboKind, Line, SwapScore, MAX_LINES, SCORES};
+use crate::board::GameState;
+
+use arrayvec::ArrayVec;
+
+pub type Mask = u128;
+
+const LANE: usize = 16;
+
+// Every column needs its own lane with a spare bit above it
+const _: () = assert!(HEIGHT < LANE && WIDTH * LANE <= Mask::BITS as usize);
+
+/// Every row of one column
+const COLUMN: u32 = (1 << HEIGHT) - 1;
+
+/// Every piece type up to the jellyfish gets a mask
+const PIECE_KINDS: usize = JELLYFISH as usize + 1;
+
+const fn every_lane(lane: u32) -> Mask {
+    let mut mask = 0;
+    let mut x = 0;
+
+    while x < WIDTH {
+        mask |= (lane as Mask) << (x * LANE);
+        x += 1;
+    }
+
+    mask
+}
+
+/// Every cell on the board
+pub const ALL: Mask = every_lane(COLUMN);
+
+#[inline(always)]
+pub fn bit(pos: usize) -> Mask {
+    1 << ((pos % WIDTH) * LANE + pos / WIDTH)
+}
+
+/// Board position of a bit in the mask
+#[inline(always)]
+pub fn pos_of(index: u32) -> usize {
+    (index as usize % LANE) * WIDTH + index as usize / LANE
+}
+
+#[inline(always)]
+fn has(mask: Mask, pos: usize) -> bool {
+    mask & bit(pos) != 0
+}
+
+/// Cells in a run of three or more, along rows and columns
+#[inline(always)]
+fn runs(mask: Mask) -> Mask {
+    let rows = mask & (mask >> LANE) & (mask >> (2 * LANE));
+    let columns = mask & (mask >> 1) & (mask >> 2);
+
+    rows | (rows << LANE) | (rows << (2 * LANE)) | columns | (columns << 1) | (columns << 2)
+}
+
+/// The 3x3 around a pufferfish
+#[inline(always)]
+fn blast(pos: usize) -> Mask {
+    let centre = bit(pos);
+    let row = centre | (centre << LANE) | (centre >> LANE);
+
+    (row | (row << 1) | (row >> 1)) & ALL
+}
+
+#[inline(always)]
+fn above_water(water_level: u8) -> Mask {
+    let under = if water_level as usize >= HEIGHT - 1 {
+        COLUMN
+    } else {
+        (1 << (water_level + 1)) - 1
+    };
+
+    every_lane(COLUMN & !under)
+}
+
+#[cfg(target_feature = "bmi2")]
+#[inline(always)]
+fn pext(value: u32, mask: u32) -> u32 {
+    unsafe { std::arch::x86_64::_pext_u32(value, mask) }
+}
+
+#[cfg(target_feature = "bmi2")]
+#[inline(always)]
+fn pdep(value: u32, mask: u32) -> u32 {
+    unsafe { std::arch::x86_64::_pdep_u32(value, mask) }
+}
+
+#[cfg(not(target_feature = "bmi2"))]
+#[inline(always)]
+fn pext(value: u32, mask: u32) -> u32 {
+    soft_pext(value, mask)
+}
+
+#[cfg(not(target_feature = "bmi2"))]
+#[inline(always)]
+fn pdep(value: u32, mask: u32) -> u32 {
+    soft_pdep(value, mask)
+}
+
+/// Gathers the bits of `value` under `mask` into the low bits
+#[inline]
+pub fn soft_pext(value: u32, mut mask: u32) -> u32 {
+    let mut out = 0;
+    let mut next = 1;
+
+    while mask != 0 {
+        let low = mask & mask.wrapping_neg();
+
+        if value & low != 0 {
+            out |= next;
+        }
+
+        next <<= 1;
+        mask ^= low;
+    }
+
+    out
+}
+
+/// Spreads the low bits of `value` out over the bits of `mask`
+#[inline]
+pub fn soft_pdep(mut value: u32, mut mask: u32) -> u32 {
+    let mut out = 0;
+
+    while mask != 0 {
+        let low = mask & mask.wrapping_neg();
+
+        if value & 1 != 0 {
+            out |= low;
+        }
+
+        value >>= 1;
+        mask ^= low;
+    }
+
+    out
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BitBoard {
+    pub pieces: [Mask; PIECE_KINDS],
+    pub water_level: u8,
+}
+
+impl BitBoard {
+    /// The pieces and water level of `game`, its tide is dropped
+    pub fn from_state(game: &GameState) -> BitBoard {
+        let mut pieces = [0; PIECE_KINDS];
+
+        for (pos, piece) in game.board.iter().enumerate() {
+            if (*piece as usize) < PIECE_KINDS {
+                pieces[*piece as usize] |= bit(pos);
+            }
+        }
+
+        BitBoard {
+            pieces,
+            water_level: game.water_level,
+        }
+    }
+
+    pub fn to_array(&self) -> [Pieces; BOARD_SIZE] {
+        let mut board = [CLEARED; BOARD_SIZE];
+
+        for (piece, mask) in self.pieces.iter().enumerate() {
+            let mut mask = *mask;
+
+            while mask != 0 {
+                board[pos_of(mask.trailing_zeros())] = piece as Pieces;
+                mask &= mask - 1;
+            }
+        }
+
+        board
+    }
+
+    #[inline(always)]
+    pub fn occupied(&self) -> Mask {
+        self.pieces.iter().fold(0, |all, mask| all | mask)
+    }
+
+    #[inline]
+    pub fn piece_at(&self, pos: usize) -> Pieces {
+        let cell = bit(pos);
+
+        match self.pieces.iter().position(|mask| mask & cell != 0) {
+            Some(piece) => piece as Pieces,
+            None => CLEARED,
+        }
+    }
+
+    /// Same moves in the same order as `GameState::get_moves`
+    #[inline]
+    pub fn get_moves(&self) -> ArrayVec<usize, MAX_MOVES> {
+        let crabs = self.pieces[CRAB as usize];
+        let movable = self.occupied() & !crabs;
+
+        // Identical neighbours can't swap, pufferfish aside
+        let same = self
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(piece, _)| *piece != PUFFERFISH as usize)
+            .fold(0, |same, (_, mask)| same | (mask & (mask >> LANE)));
+
+        let mut pairs = movable & (movable >> LANE) & !same;
+        let mut moves = ArrayVec::new();
+
+        while pairs != 0 {
+            moves.push(pos_of(pairs.trailing_zeros()));
+            pairs &= pairs - 1;
+        }
+
+        moves.sort_unstable();
+        moves
+    }
+
+    #[inline(always)]
+    fn remove(&mut self, mask: Mask) {
+        for pieces in self.pieces.iter_mut() {
+            *pieces &= !mask;
+        }
+    }
+
+    /// Floats every column up to the top row
+    #[inline]
+    pub fn gravity(&mut self) {
+        let occupied = self.occupied();
+
+        for x in 0..WIDTH {
+            let shift = x * LANE;
+            let lane = (occupied >> shift) as u32 & COLUMN;
+            let packed = COLUMN & !(COLUMN >> lane.count_ones());
+
+            if lane == packed {
+                continue;
+            }
+
+            for pieces in self.pieces.iter_mut() {
+                let column = (*pieces >> shift) as u32 & COLUMN;
+
+                if column == 0 {
+                    continue;
+                }
+
+                let moved = pdep(pext(column, lane), packed);
+                *pieces = (*pieces & !((COLUMN as Mask) << shift)) | ((moved as Mask) << shift);
+            }
+        }
+    }
+
+    /// Every line and every crab out of the water, with the crab bonus
+    #[inline]
+    fn find_clears(&self) -> (Mask, f32) {
+        let lines = self.pieces[..=WAVY_SQUARE as usize]
+            .iter()
+            .fold(0, |lines, mask| lines | runs(*mask));
+
+        let crabs = self.pieces[CRAB as usize] & above_water(self.water_level);
+        let bonus = crabs.count_ones() as f32 * rules::crab_score(self.water_level);
+
+        (lines | crabs, bonus)
+    }
+
+    /// Same as `scoring::cascade_score`, the lines are found per piece
+    fn cascade_score(&self, mask: Mask) -> i32 {
+        let mut lines: ArrayVec<Line, MAX_LINES> = ArrayVec::new();
+
+        for pieces in self.pieces[..=WAVY_SQUARE as usize].iter() {
+            let marked = pieces & mask;
+
+            for step in [LANE, 1] {
+                let starts = marked & (marked >> step) & (marked >> (2 * step));
+                let cells = starts | (starts << step) | (starts << (2 * step));
+                let mut heads = cells & !(cells << step);
+
+                while heads != 0 && !lines.is_full() {
+                    let head = heads & heads.wrapping_neg();
+                    heads ^= head;
+
+                    let mut line = Line { cells: 0, length: 0 };
+                    let mut next = head;
+
+                    while cells & next != 0 {
+                        line.cells |= next;
+                        line.length += 1;
+                        next <<= step;
+                    }
+
+                    lines.push(line);
+                }
+            }
+        }
+
+        scoring::group_score(&lines)
+    }
+
+    /// Clears and refloats until the board is still, like `clean_board`
+    #[inline]
+    pub fn cascade(&mut self) -> f32 {
+        let mut score = 0.0;
+
+        loop {
+            let (mask, bonus) = self.find_clears();
+
+            if mask == 0 {
+                return score;
+            }
+
+            score += self.cascade_score(mask) as f32;
+            score += bonus;
+
+            self.remove(mask);
+            self.gravity();
+        }
+    }
+
+    /// Score of the lines the two swapped pieces make, see `get_combo`
+    fn get_combo(&self, pos: usize) -> (i32, ComboKind) {
+        let x = pos % WIDTH;
+        let left_piece = self.pieces[self.piece_at(pos) as usize];
+        let right_piece = self.pieces[self.piece_at(pos + 1) as usize];
+
+        let mut left = 0;
+        let mut right = 0;
+        let mut l_col = 1;
+        let mut r_col = 1;
+
+        if x >= 2 && has(left_piece, pos - 1) && has(left_piece, pos - 2) {
+            left = 3;
+        }
+
+        if x + 3 <= LAST_COLUMN && has(right_piece, pos + 2) && has(right_piece, pos + 3) {
+            right = 3;
+        }
+
+        if pos >= WIDTH && has(left_piece, pos - WIDTH) {
+            l_col += 1;
+            if pos >= 2 * WIDTH && has(left_piece, pos - 2 * WIDTH) {
+                l_col += 1;
+            }
+        }
+
+        if pos + WIDTH < BOARD_SIZE && has(left_piece, pos + WIDTH) {
+            l_col += 1;
+            if pos + 2 * WIDTH < BOARD_SIZE && has(left_piece, pos + 2 * WIDTH) {
+                l_col += 1;
+            }
+        }
+
+        if pos + 1 >= WIDTH && has(right_piece, pos + 1 - WIDTH) {
+            r_col += 1;
+            if pos + 1 >= 2 * WIDTH && has(right_piece, pos + 1 - 2 * WIDTH) {
+                r_col += 1;
+            }
+        }
+
+        if pos + 1 + WIDTH < BOARD_SIZE && has(right_piece, pos + 1 + WIDTH) {
+            r_col += 1;
+            if pos + 1 + 2 * WIDTH < BOARD_SIZE && has(right_piece, pos + 1 + 2 * WIDTH) {
+                r_col += 1;
+            }
+        }
+
+        if r_col < 3 {
+            r_col = 0;
+        }
+        if l_col < 3 {
+            l_col = 0;
+        }
+
+        scoring::swap_combo(left, right, l_col, r_col)
+    }
+
+    /// Clears `mask` as a special piece going off, scoring a point a cell
+    #[inline]
+    fn go_off(&mut self, mask: Mask, kind: ComboKind) -> f32 {
+        self.remove(mask);
+        self.gravity();
+
+        SCORES.cleared(kind, mask.count_ones() as usize) + self.cascade()
+    }
+
+    /// Same as `GameState::validate_swap`
+    pub fn validate_swap(&self, pos: usize) -> Result<(), IllegalMove> {
+        if pos >= BOARD_SIZE {
+            return Err(IllegalMove::Null);
+        }
+
+        if pos % WIDTH == LAST_COLUMN {
+            return Err(IllegalMove::EdgeColumn);
+        }
+
+        rules::check_swap(self.piece_at(pos), self.piece_at(pos + 1))
+    }
+
+    /// Same as `GameState::swap`
+    pub fn swap(&mut self, pos: usize) -> Result<SwapScore, IllegalMove> {
+        self.validate_swap(pos)?;
+
+        let one = self.piece_at(pos);
+        let two = self.piece_at(pos + 1);
+
+        let (score, combo) = if one == PUFFERFISH && two == PUFFERFISH {
+            (self.go_off(blast(pos) | blast(pos + 1), ComboKind::Pufferfish), ComboKind::Pufferfish)
+        } else if rules::jelly_meets_puffer(one, two) {
+            let mut puffers = self.pieces[PUFFERFISH as usize];
+            let mut mask = puffers;
+
+            while puffers != 0 {
+                mask |= blast(pos_of(puffers.trailing_zeros()));
+                puffers &= puffers - 1;
+            }
+
+            (self.go_off(mask, ComboKind::Jellyfish), ComboKind::Jellyfish)
+        } else if one == PUFFERFISH || two == PUFFERFISH {
+            let puffer = if one == PUFFERFISH { pos } else { pos + 1 };
+            (self.go_off(blast(puffer), ComboKind::Pufferfish), ComboKind::Pufferfish)
+        } else if one == JELLYFISH || two == JELLYFISH {
+            let target = if one == JELLYFISH { two } else { one };
+            let mask = self.pieces[target as usize];
+            (self.go_off(mask, ComboKind::Jellyfish), ComboKind::Jellyfish)
+        } else {
+            let pair = bit(pos) | bit(pos + 1);
+            self.pieces[one as usize] ^= pair;
+            self.pieces[two as usize] ^= pair;
+
+            let (combo, kind) = self.get_combo(pos);
+            let mut score = combo as f32;
+
+            if score > 0.0 {
+                score += self.cascade();
+            }
+
+            (score, kind)
+        };
+
+        Ok(SwapScore { score, combo })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{board_from_str, generate_settled_board};
+    use crate::config::TEST_BOARD;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_round_trip() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let bits = BitBoard::from_state(&game);
+
+        assert_eq!(bits.to_array(), game.board);
+        assert_eq!(bits.occupied(), ALL);
+        assert_eq!(bits.get_moves(), game.get_moves());
+
+        for pos in 0..72 {
+            assert_eq!(pos_of(bit(pos).trailing_zeros()), pos);
+            assert_eq!(bits.piece_at(pos), game.board[pos]);
+        }
+    }
+
+    #[test]
+    fn test_soft_pext_pdep() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..10_000 {
+            let value = rng.gen::<u32>() & COLUMN;
+            let mask = rng.gen::<u32>() & COLUMN;
+
+            let gathered = soft_pext(value, mask);
+            assert_eq!(gathered.count_ones(), (value & mask).count_ones());
+            assert_eq!(soft_pdep(gathered, mask), value & mask);
+            assert_eq!(pext(value, mask), gathered);
+            assert_eq!(pdep(gathered, mask), value & mask);
+        }
+    }
+
+    #[test]
+    fn test_swap_matches_engine() {
+        let mut rng = StdRng::seed_from_u64(39);
+
+        for seed in 0..1_000u32 {
+            let game = generate_settled_board(&mut rng, (seed % 6) as u8);
+            let bits = BitBoard::from_state(&game);
+
+            assert_eq!(bits.get_moves(), game.get_moves());
+
+            for pos in game.get_moves() {
+                let mut engine = game;
+                let mut board = bits;
+
+                let expected = engine.swap(pos);
+                let got = board.swap(pos);
+
+                assert_eq!(got, expected, "seed {} move {}", seed, pos);
+                assert_eq!(board.to_array(), engine.board, "seed {} move {}", seed, pos);
+
+                // Keep going from the cleared board, empty cells and all
+                if let Some(next) = engine.get_moves().first() {
+                    assert_eq!(board.get_moves(), engine.get_moves());
+                    assert_eq!(board.swap(*next), engine.swap(*next));
+                    assert_eq!(board.to_array(), engine.board);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! A position book, every move ranked for boards searched ahead of time so
+//! a board the bot has seen before is answered without searching it again.
+//! `bilgebot book build` fills one from a corpus and with `--book`
+//! `find_best_move_list` answers from it whenever the book went at least as
+//! deep as asked, before it searches anything.
+//!
+//! Boards are keyed by `position_hash`, which covers the water and tide as
+//! well since the same board plays differently under them. On disk a book
+//! is a short header, the magic `BOOK`, the version and the entry count,
+//! then the entries sorted by hash, all little endian. Each entry is its
+//! hash, depth and number of moves followed by the moves best first:
+//!
+//! ```text
+//! hash u64 | depth u8 | moves u8 | (move u8 | score f32) * moves
+//! ```
+
+use crate::board::searcher::{Info, TurnList};
+use crate::board::GameState;
+
+use std::convert::TryInto;
+use std::path::Path;
+
+/// Bumped whenever a book written by an older build can't be read
+pub const BOOK_VERSION: u32 = 2;
+
+const MAGIC: &[u8; 4] = b"BOOK";
+const HEADER_SIZE: usize = 12;
+/// Hash, depth and move count
+const ENTRY_HEADER_SIZE: usize = 10;
+const MOVE_SIZE: usize = 5;
+
+/// FNV-1a over the board, water level and tide. It has to come out the
+/// same on every run, unlike the `RandomState` the hash table uses.
+pub fn position_hash(game: &GameState) -> u64 {
+    let tide = game.tide.pump_score.to_le_bytes();
+    let extra = [game.water_level, game.tide.rise, tide[0], tide[1]];
+
+    game.board
+        .iter()
+        .chain(extra.iter())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookEntry {
+    pub hash: u64,
+    pub depth: u8,
+    /// Every legal move and its score, best first
+    pub turns: Vec<(u8, f32)>,
+}
+
+/// Entries sorted by hash, one per position
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Book {
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    pub fn new() -> Book {
+        Book::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, game: &GameState) -> Option<&BookEntry> {
+        let hash = position_hash(game);
+
+        self.entries
+            .binary_search_by_key(&hash, |entry| entry.hash)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// Adds the ranking a `depth` deep search of `game` found, keeping
+    /// whichever of the old and new entry went deeper
+    pub fn insert(&mut self, game: &GameState, ranking: &TurnList, depth: u8) {
+        let entry = BookEntry {
+            hash: position_hash(game),
+            depth,
+            turns: ranking
+                .turns
+                .iter()
+                .map(|info| (info.turn as u8, info.score))
+                .collect(),
+        };
+
+        match self.entries.binary_search_by_key(&entry.hash, |entry| entry.hash) {
+            Ok(index) if self.entries[index].depth <= depth => self.entries[index] = entry,
+            Ok(_) => {}
+            Err(index) => self.entries.insert(index, entry),
+        }
+    }
+
+    /// The book's ranking of every move of `game` when it was searched at
+    /// least `depth` deep, in the same form as a search's
+    pub fn probe(&self, game: &GameState, depth: u8) -> Option<TurnList> {
+        let entry = self.get(game).filter(|entry| entry.depth >= depth)?;
+
+        // Guards against the odd hash collision, the moves have to be
+        // exactly the ones the board has
+        let moves = game.get_moves();
+        if entry.turns.len() != moves.len() {
+            return None;
+        }
+
+        let turns = entry
+            .turns
+            .iter()
+            .map(|(turn, score)| {
+                let turn = *turn as usize;
+                let mut copy = *game;
+                let combo = copy.swap(turn).ok()?.combo;
+
+                Some(Info {
+                    turn,
+                    score: *score,
+                    combo,
+                })
+            })
+            .collect::<Option<Vec<Info>>>()?;
+
+        if moves.iter().any(|turn| !turns.iter().any(|info| info.turn == *turn)) {
+            return None;
+        }
+
+        Some(TurnList {
+            turns,
+            positions: 0,
+            hash_hits: 0,
+            info_str: "Found in the book.".to_string(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let size: usize = self
+            .entries
+            .iter()
+            .map(|entry| ENTRY_HEADER_SIZE + entry.turns.len() * MOVE_SIZE)
+            .sum();
+        let mut out = Vec::with_capacity(HEADER_SIZE + size);
+
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&BOOK_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+
+        for entry in self.entries.iter() {
+            out.extend_from_slice(&entry.hash.to_le_bytes());
+            out.push(entry.depth);
+            out.push(entry.turns.len() as u8);
+
+            for (turn, score) in entry.turns.iter() {
+                out.push(*turn);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Book, String> {
+        if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+            return Err("Not a position book".to_string());
+        }
+
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+
+        if version != BOOK_VERSION {
+            return Err(format!(
+                "Book is version {}, this build reads version {}, build a new one",
+                version, BOOK_VERSION
+            ));
+        }
+
+        let count = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+        let mut body = &data[HEADER_SIZE..];
+        let mut entries: Vec<BookEntry> = Vec::new();
+
+        for number in 0..count {
+            let short = || format!("Book should hold {} entries, it ends in entry {}", count, number + 1);
+
+            if body.len() < ENTRY_HEADER_SIZE {
+                return Err(short());
+            }
+
+            let moves = body[9] as usize;
+            let size = ENTRY_HEADER_SIZE + moves * MOVE_SIZE;
+
+            if body.len() < size {
+                return Err(short());
+            }
+
+            entries.push(BookEntry {
+                hash: u64::from_le_bytes(body[0..8].try_into().unwrap()),
+                depth: body[8],
+                turns: body[ENTRY_HEADER_SIZE..size]
+                    .chunks_exact(MOVE_SIZE)
+                    .map(|chunk| (chunk[0], f32::from_le_bytes(chunk[1..5].try_into().unwrap())))
+                    .collect(),
+            });
+
+            body = &body[size..];
+        }
+
+        if !body.is_empty() {
+            return Err(format!("Book has {} bytes past its {} entries", body.len(), count));
+        }
+
+        if entries.windows(2).any(|pair| pair[0].hash >= pair[1].hash) {
+            return Err("Book entries are out of order".to_string());
+        }
+
+        Ok(Book { entries })
+    }
+
+    pub fn load(path: &Path) -> Result<Book, String> {
+        let data = std::fs::read(path)
+            .map_err(|err| format!("Could not read book {}: {}", path.display(), err))?;
+
+        Book::from_bytes(&data).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_bytes())
+            .map_err(|err| format!("Could not save book {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_from_str;
+    use crate::board::searcher::{find_best_move_list_in, new_hash_table, SearchPool};
+    use crate::config::TEST_BOARD;
+    use std::sync::Arc;
+
+    /// The moves of `game` ranked by a depth 2 search, no book involved
+    fn ranking(game: &GameState) -> TurnList {
+        find_best_move_list_in(game, 2, false, &new_hash_table(1000), &SearchPool::default())
+    }
+
+    #[test]
+    fn test_position_hash() {
+        let game = board_from_str(TEST_BOARD, 3);
+
+        let mut wetter = game;
+        wetter.water_level = 4;
+
+        let mut rising = game;
+        rising.tide.rise = 1;
+
+        let mut changed = game;
+        changed.board[0] = (changed.board[0] + 1) % 6;
+
+        let hash = position_hash(&game);
+        assert_eq!(hash, position_hash(&board_from_str(TEST_BOARD, 3)));
+
+        for other in [wetter, rising, changed].iter() {
+            assert_ne!(hash, position_hash(other));
+        }
+    }
+
+    #[test]
+    fn test_book_round_trip() {
+        let mut book = Book::new();
+        let game = board_from_str(TEST_BOARD, 3);
+        let drier = board_from_str(TEST_BOARD, 1);
+
+        let ranked = ranking(&game);
+        book.insert(&game, &ranked, 6);
+        book.insert(&drier, &ranking(&drier), 5);
+
+        // Shallower results don't replace deeper ones
+        let mut shallow = ranked.clone();
+        shallow.turns.reverse();
+        book.insert(&game, &shallow, 4);
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.get(&game).unwrap().turns[0].0 as usize, ranked.turns[0].turn);
+
+        let read = Book::from_bytes(&book.to_bytes()).unwrap();
+        assert_eq!(read, book);
+
+        let found = read.probe(&game, 6).unwrap();
+        assert_eq!(found.turns, ranked.turns);
+        assert_eq!(found.positions, 0);
+        assert!(read.probe(&game, 7).is_none());
+
+        let mut bytes = book.to_bytes();
+        bytes[4] = 9;
+        assert!(Book::from_bytes(&bytes).is_err());
+
+        let bytes = book.to_bytes();
+        assert!(Book::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Book::from_bytes(&[&bytes[..], &[0]].concat()).is_err());
+        assert!(Book::from_bytes(b"nonsense").is_err());
+    }
+
+    #[test]
+    fn test_probe_checks_moves() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let mut book = Book::new();
+
+        // A ranking missing a move isn't this board's
+        let mut partial = ranking(&game);
+        partial.turns.pop();
+        book.insert(&game, &partial, 2);
+        assert!(book.probe(&game, 2).is_none());
+
+        // Nor is one with a move the board can't play
+        let mut wrong = ranking(&game);
+        wrong.turns[0].turn = 5;
+        book.insert(&game, &wrong, 2);
+        assert!(book.probe(&game, 2).is_none());
+    }
+
+    #[test]
+    fn test_search_uses_book() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let hash_table = new_hash_table(1000);
+
+        let mut ranked = ranking(&game);
+        ranked.turns.iter_mut().for_each(|info| info.score -= 1000.0);
+
+        let mut book = Book::new();
+        book.insert(&game, &ranked, 2);
+
+        let mut pool = SearchPool::default();
+        pool.book = Some(Arc::new(book));
+
+        // Every move comes back, straight from the book
+        let list = find_best_move_list_in(&game, 2, false, &hash_table, &pool);
+        assert_eq!(list.turns, ranked.turns);
+        assert_eq!(list.turns.len(), game.get_moves().len());
+        assert_eq!(list.positions, 0);
+
+        // Deeper than the book went is searched
+        let list = find_best_move_list_in(&game, 3, false, &hash_table, &pool);
+        assert_eq!(list.turns.len(), game.get_moves().len());
+        assert!(list.positions > 0);
+    }
+}
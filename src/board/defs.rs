@@ -1,4 +1,26 @@
-pub const WIDTH: i16 = 6;
+/// The board is 6 by 12 unless the `small-board` feature picks the second
+/// preset. Masks, lookup tables and move ranges are all built from these.
+#[cfg(not(feature = "small-board"))]
+pub const WIDTH: usize = 6;
+#[cfg(not(feature = "small-board"))]
+pub const HEIGHT: usize = 12;
+
+#[cfg(feature = "small-board")]
+pub const WIDTH: usize = 5;
+#[cfg(feature = "small-board")]
+pub const HEIGHT: usize = 8;
+
+pub const BOARD_SIZE: usize = WIDTH * HEIGHT;
+
+// Clear masks are a u64 and a u16, and a line needs three cells each way
+const _: () = assert!(BOARD_SIZE <= 80);
+const _: () = assert!(WIDTH >= 3 && HEIGHT >= 3);
+
+/// The right hand column, which has nothing to swap with
+pub const LAST_COLUMN: usize = WIDTH - 1;
+
+/// Most moves a board can have, every cell but the last column
+pub const MAX_MOVES: usize = LAST_COLUMN * HEIGHT;
 
 pub type Pieces = u8;
 
@@ -1,30 +1,32 @@
 use crate::board::defs::*;
+use crate::board::rules::{self, Tide};
 use crate::board::GameState;
 
 use rand::Rng;
 
 #[cold]
 pub fn generate_rand_board() -> GameState {
-    let mut board = [CLEARED; 6 * 12];
+    let mut board = [CLEARED; BOARD_SIZE];
     let mut rng = rand::thread_rng();
 
     let mut last: Option<Pieces> = None;
     for x in board.iter_mut() {
         if let Some(pce) = last {
-            let mut to_use = piece_from_num(rng.gen_range(1..7));
+            let mut to_use = piece_from_num(rng.gen_range(0..7));
             while to_use == pce {
-                to_use = piece_from_num(rng.gen_range(1..7));
+                to_use = piece_from_num(rng.gen_range(0..7));
             }
             last = Some(to_use);
             *x = to_use;
             continue;
         }
-        *x = piece_from_num(rng.gen_range(1..7));
+        *x = piece_from_num(rng.gen_range(0..7));
         last = Some(*x);
     }
 
     GameState {
         water_level: 3,
+        tide: Tide::default(),
         board,
         to_clear_l: 0,
         to_clear_r: 0,
@@ -36,9 +38,10 @@ pub fn copy_board(copying: &GameState) -> GameState {
 }
 
 #[cold]
-pub fn board_from_array(board: [Pieces; 6 * 12]) -> GameState {
+pub fn board_from_array(board: [Pieces; BOARD_SIZE]) -> GameState {
     GameState {
         water_level: 3,
+        tide: Tide::default(),
         board,
         to_clear_l: 0,
         to_clear_r: 0,
@@ -47,12 +50,13 @@ pub fn board_from_array(board: [Pieces; 6 * 12]) -> GameState {
 
 #[cold]
 pub fn board_from_str(in_str: &str, water_level: u8) -> GameState {
-    let mut board = [NULL; 72];
+    let mut board = [NULL; BOARD_SIZE];
     let brd = str_to_enum(in_str);
     board.copy_from_slice(&brd[..]);
 
     GameState {
         water_level,
+        tide: Tide::default(),
         board,
         to_clear_l: 0,
         to_clear_r: 0,
@@ -63,7 +67,62 @@ pub fn board_from_str(in_str: &str, water_level: u8) -> GameState {
 pub fn generate_game() -> GameState {
     GameState {
         water_level: 3,
-        board: [CLEARED; 6 * 12],
+        tide: Tide::default(),
+        board: [CLEARED; BOARD_SIZE],
+        to_clear_l: 0,
+        to_clear_r: 0,
+    }
+}
+
+/// Drops fresh random pieces into every cleared cell, like the game does
+/// once a move has finished resolving
+pub fn fill_cleared(game: &mut GameState) {
+    let mut rng = rand::thread_rng();
+
+    for x in game.board.iter_mut() {
+        if *x == CLEARED {
+            *x = piece_from_num(rng.gen_range(0..7));
+        }
+    }
+}
+
+/// Random board with no lines of three already on it, as a real board
+/// looks between moves. A few crabs are dropped at or below the water line
+/// and the odd pufferfish or jellyfish anywhere.
+pub fn generate_settled_board<R: Rng>(rng: &mut R, water_level: u8) -> GameState {
+    let mut board = [CLEARED; BOARD_SIZE];
+
+    for pos in 0..board.len() {
+        let x = pos % WIDTH;
+        let y = pos / WIDTH;
+
+        loop {
+            let roll = rng.gen_range(0..100);
+
+            let piece = if roll < 3 && rules::underwater(y, water_level) {
+                CRAB
+            } else if roll == 3 {
+                PUFFERFISH
+            } else if roll == 4 {
+                JELLYFISH
+            } else {
+                piece_from_num(rng.gen_range(0..7))
+            };
+
+            let row = x >= 2 && board[pos - 1] == piece && board[pos - 2] == piece;
+            let col = y >= 2 && board[pos - WIDTH] == piece && board[pos - 2 * WIDTH] == piece;
+
+            if !(row || col) || piece == CRAB || piece == PUFFERFISH || piece == JELLYFISH {
+                board[pos] = piece;
+                break;
+            }
+        }
+    }
+
+    GameState {
+        water_level,
+        tide: Tide::default(),
+        board,
         to_clear_l: 0,
         to_clear_r: 0,
     }
@@ -4,18 +4,18 @@ use crate::board::Move;
 #[inline(always)]
 pub fn can_move(piece: Pieces) -> bool {
     // Special pieces are all 7 or above (saves multiple checks)
-    (piece as u8) < 7
+    piece < 7
 }
 
-const fn build_x_arr() -> [u8; 72] {
-    let mut end = [0; 72];
+const fn build_x_arr() -> [u8; BOARD_SIZE] {
+    let mut end = [0; BOARD_SIZE];
     let mut cntr = 0;
 
     loop {
-        end[cntr] = (cntr % 6) as u8;
+        end[cntr] = (cntr % WIDTH) as u8;
 
         cntr += 1;
-        if cntr >= 72 {
+        if cntr >= BOARD_SIZE {
             break;
         }
     }
@@ -23,15 +23,15 @@ const fn build_x_arr() -> [u8; 72] {
     end
 }
 
-const fn build_y_arr() -> [u8; 72] {
-    let mut end = [0; 72];
+const fn build_y_arr() -> [u8; BOARD_SIZE] {
+    let mut end = [0; BOARD_SIZE];
     let mut cntr = 0;
 
     loop {
-        end[cntr] = (cntr / 6) as u8;
+        end[cntr] = (cntr / WIDTH) as u8;
 
         cntr += 1;
-        if cntr >= 72 {
+        if cntr >= BOARD_SIZE {
             break;
         }
     }
@@ -39,8 +39,8 @@ const fn build_y_arr() -> [u8; 72] {
     end
 }
 
-pub const X_ARR: [u8; 72] = build_x_arr();
-pub const Y_ARR: [u8; 72] = build_y_arr();
+pub const X_ARR: [u8; BOARD_SIZE] = build_x_arr();
+pub const Y_ARR: [u8; BOARD_SIZE] = build_y_arr();
 
 #[inline]
 pub fn x_pos_fast(x: usize) -> usize {
@@ -52,11 +52,11 @@ pub fn y_pos_fast(x: usize) -> usize {
     *safe_get!(Y_ARR, x) as usize
 }
 
-pub const PUFFER: [(u64, u16); 72] = build_puffers();
+pub const PUFFER: [(u64, u16); BOARD_SIZE] = build_puffers();
 
 macro_rules! apply_to_pair {
     ($pair:expr, $pos:expr) => {
-        if $pos >= 64 {
+        if $pos > 63 {
             $pair.1 |= 1 << ($pos - 64);
         } else {
             $pair.0 |= 1 << $pos;
@@ -64,28 +64,28 @@ macro_rules! apply_to_pair {
     };
 }
 
-pub const fn build_puffers() -> [(u64, u16); 72] {
-    let mut end = [(0, 0); 72];
+pub const fn build_puffers() -> [(u64, u16); BOARD_SIZE] {
+    let mut end = [(0, 0); BOARD_SIZE];
     let mut pos = 0;
 
     loop {
-        let x = X_ARR[pos];
-        let y = Y_ARR[pos];
+        let x = X_ARR[pos] as usize;
+        let y = Y_ARR[pos] as usize;
 
         let mut pair = (0, 0);
 
         apply_to_pair!(pair, pos);
 
         let up = y > 0;
-        let down = y < 11;
-        let right = x < 5;
+        let down = y < HEIGHT - 1;
+        let right = x < LAST_COLUMN;
         let left = x > 0;
 
         if up {
-            apply_to_pair!(pair, pos - 6);
+            apply_to_pair!(pair, pos - WIDTH);
         }
         if down {
-            apply_to_pair!(pair, pos + 6);
+            apply_to_pair!(pair, pos + WIDTH);
         }
         if left {
             apply_to_pair!(pair, pos - 1);
@@ -95,22 +95,22 @@ pub const fn build_puffers() -> [(u64, u16); 72] {
         }
 
         if up && right {
-            apply_to_pair!(pair, pos - 5);
+            apply_to_pair!(pair, pos - WIDTH + 1);
         }
         if up && left {
-            apply_to_pair!(pair, pos - 7);
+            apply_to_pair!(pair, pos - WIDTH - 1);
         }
         if down && right {
-            apply_to_pair!(pair, pos + 7);
+            apply_to_pair!(pair, pos + WIDTH + 1);
         }
         if down && left {
-            apply_to_pair!(pair, pos + 5);
+            apply_to_pair!(pair, pos + WIDTH - 1);
         }
 
         end[pos] = pair;
 
         pos += 1;
-        if pos >= 72 {
+        if pos >= BOARD_SIZE {
             break;
         }
     }
@@ -121,57 +121,40 @@ pub const fn build_puffers() -> [(u64, u16); 72] {
 #[inline]
 pub fn move_to_dani_move(movement: usize) -> usize {
     let x = x_pos_fast(movement);
-    let y = 12 - y_pos_fast(movement);
+    let y = HEIGHT - y_pos_fast(movement);
 
-    (y * 5) + x
+    (y * LAST_COLUMN) + x
 }
 
 #[inline]
 pub fn int_to_move(move_num: usize) -> Move {
     Move {
-        y: (move_num - 1) / 5,
-        x: (move_num - 1) % 5,
+        y: (move_num - 1) / LAST_COLUMN,
+        x: (move_num - 1) % LAST_COLUMN,
     }
 }
 
 #[inline(always)]
 pub fn int_to_mover(move_num: usize) -> Move {
     Move {
-        y: (move_num) / 6,
-        x: (move_num) % 6,
+        y: (move_num) / WIDTH,
+        x: (move_num) % WIDTH,
     }
 }
 
 #[inline(always)]
 pub fn move_to_int(move_num: &Move) -> usize {
-    move_num.x + (move_num.y * WIDTH as usize)
-}
-
-macro_rules! promote_scorers {
-    ($x:expr) => {
-        match $x {
-            2 => 2,
-            3 => 100,
-            4 => 10000,
-            _ => $x,
-        }
-    };
+    move_num.x + (move_num.y * WIDTH)
 }
 
 macro_rules! row_score {
     ($x:expr) => {
-        match $x {
-            3 => 3,
-            4 => 5,
-            5 => 7,
-            _ => 0,
-        }
+        $crate::board::scoring::SCORES.line_score($x as usize)
     };
 }
 
-
-pub const fn build_set_masks() -> [(u64, u16); 72] {
-    let mut end = [(0, 0); 72];
+pub const fn build_set_masks() -> [(u64, u16); BOARD_SIZE] {
+    let mut end = [(0, 0); BOARD_SIZE];
     let mut pos = 0;
 
     loop {
@@ -188,11 +171,11 @@ pub const fn build_set_masks() -> [(u64, u16); 72] {
         end[pos] = pair;
 
         pos += 1;
-        if pos >= 72 {
+        if pos >= BOARD_SIZE {
             break;
         }
     }
     end
 }
 
-pub const SET_BIT_MASKS: [(u64, u16); 72] = build_set_masks();
\ No newline at end of file
+pub const SET_BIT_MASKS: [(u64, u16); BOARD_SIZE] = build_set_masks();
\ No newline at end of file
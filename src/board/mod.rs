@@ -1,15 +1,27 @@
 #[macro_use]
 pub mod helpers;
 
+pub mod animate;
+pub mod bitboard;
+pub mod book;
 pub mod defs;
+#[cfg(all(test, not(feature = "small-board")))]
+mod differential;
 pub mod generator;
+pub mod persist;
+pub mod reference;
+pub mod render;
+pub mod rules;
+pub mod scoring;
 pub mod searcher;
 pub mod structure;
+pub mod trace;
 pub mod transforms;
 pub mod transforms_beta;
+#[cfg(feature = "png")]
+pub mod vision;
 
 pub use generator::*;
 pub use helpers::*;
 pub use structure::*;
-pub use transforms::*;
 pub use transforms_beta::*;
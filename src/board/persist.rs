@@ -0,0 +1,434 @@
+//! Saves the transposition table to a file and reads it back, so a bot run
+//! with `serve --table-file` starts where the last run stopped instead of
+//! with a cold table.
+//!
+//! The file is a header, the magic `BTAB` and the version, then the
+//! entries and last a trailer with the entry count and a checksum of
+//! everything before it. Boards are packed two pieces to a byte, low nibble
+//! first, and every entry keeps the tide it was searched under, 45 bytes
+//! in all:
+//!
+//! ```text
+//! board [u8; 36] | score f32 | depth u8 | water u8 | rise u8 | pump u16
+//! ```
+//!
+//! All numbers are little endian and the checksum is FNV-1a. A file from
+//! another version, cut short or with a bad checksum is refused whole.
+//! Saving and loading both stream the entries, the save runs while the bot
+//! is being shut down and a second copy of a big table may not fit.
+
+use crate::board::defs::*;
+use crate::board::rules::Tide;
+use crate::board::searcher::{HashEntry, HashTable};
+use crate::board::Board;
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Bumped whenever a table saved by an older build can't be read
+pub const TABLE_VERSION: u32 = 2;
+
+const MAGIC: &[u8; 4] = b"BTAB";
+const HEADER_SIZE: usize = 8;
+const PACKED_BOARD: usize = 36;
+const ENTRY_SIZE: usize = PACKED_BOARD + 9;
+const TRAILER_SIZE: usize = 16;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Writer which keeps a checksum of everything through it
+struct Checksummed<W: Write> {
+    inner: W,
+    hash: u64,
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hash = fnv(self.hash, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `None` for boards with a piece that doesn't fit in a nibble, which only
+/// a bad board string makes
+fn pack(board: &Board) -> Option<[u8; PACKED_BOARD]> {
+    let mut packed = [0; PACKED_BOARD];
+
+    for (byte, pair) in packed.iter_mut().zip(board.chunks_exact(2)) {
+        if pair[0] > CLEARED || pair[1] > CLEARED {
+            return None;
+        }
+
+        *byte = pair[0] | (pair[1] << 4);
+    }
+
+    Some(packed)
+}
+
+fn unpack(packed: &[u8]) -> Option<Board> {
+    let mut board = [CLEARED; BOARD_SIZE];
+
+    for (pair, byte) in board.chunks_exact_mut(2).zip(packed.iter()) {
+        pair[0] = byte & 0xf;
+        pair[1] = byte >> 4;
+
+        if pair[0] > CLEARED || pair[1] > CLEARED {
+            return None;
+        }
+    }
+
+    Some(board)
+}
+
+fn entry_bytes(packed: &[u8; PACKED_BOARD], entry: &HashEntry) -> [u8; ENTRY_SIZE] {
+    let mut bytes = [0; ENTRY_SIZE];
+    let pump = entry.tide.pump_score.to_le_bytes();
+
+    bytes[..PACKED_BOARD].copy_from_slice(packed);
+    bytes[PACKED_BOARD..PACKED_BOARD + 4].copy_from_slice(&entry.score.to_le_bytes());
+    bytes[PACKED_BOARD + 4..].copy_from_slice(&[
+        entry.depth,
+        entry.water_level,
+        entry.tide.rise,
+        pump[0],
+        pump[1],
+    ]);
+
+    bytes
+}
+
+fn read_entry(bytes: &[u8; ENTRY_SIZE]) -> Option<(Board, HashEntry)> {
+    let board = unpack(&bytes[..PACKED_BOARD])?;
+    let rest = &bytes[PACKED_BOARD..];
+
+    Some((
+        board,
+        HashEntry {
+            score: f32::from_le_bytes(rest[0..4].try_into().unwrap()),
+            depth: rest[4],
+            water_level: rest[5],
+            tide: Tide {
+                rise: rest[6],
+                pump_score: u16::from_le_bytes(rest[7..9].try_into().unwrap()),
+            },
+        },
+    ))
+}
+
+/// The shallowest depth saved when only `max_entries` fit, and how many
+/// entries of that depth fit after every deeper one
+fn depth_cutoff(at_depth: &[usize; 256], max_entries: usize) -> (u8, usize) {
+    let mut deeper = 0;
+
+    for depth in (0..256).rev() {
+        if deeper + at_depth[depth] >= max_entries {
+            return (depth as u8, max_entries - deeper);
+        }
+
+        deeper += at_depth[depth];
+    }
+
+    (0, at_depth[0])
+}
+
+/// Writes out at most `max_entries` entries of the table, the deepest when
+/// there are more, returning how many were written. The table is walked
+/// twice, once to count the entries at each depth and once to write them.
+pub fn write_table<W: Write>(
+    table: &HashTable,
+    out: W,
+    max_entries: usize,
+) -> std::io::Result<usize> {
+    let mut at_depth = [0; 256];
+
+    for item in table.iter() {
+        at_depth[item.value().depth as usize] += 1;
+    }
+
+    let (cutoff, mut room_at_cutoff) = depth_cutoff(&at_depth, max_entries);
+
+    let mut out = Checksummed {
+        inner: out,
+        hash: FNV_OFFSET,
+    };
+
+    out.write_all(MAGIC)?;
+    out.write_all(&TABLE_VERSION.to_le_bytes())?;
+
+    let mut saved = 0;
+
+    for item in table.iter() {
+        let entry = item.value();
+
+        // Entries added since the count are saved as long as there's room
+        if saved == max_entries || entry.depth < cutoff {
+            continue;
+        }
+
+        if entry.depth == cutoff {
+            if room_at_cutoff == 0 {
+                continue;
+            }
+
+            room_at_cutoff -= 1;
+        }
+
+        if let Some(packed) = pack(item.key()) {
+            out.write_all(&entry_bytes(&packed, entry))?;
+            saved += 1;
+        }
+    }
+
+    out.write_all(&(saved as u64).to_le_bytes())?;
+
+    let checksum = out.hash;
+    out.inner.write_all(&checksum.to_le_bytes())?;
+    out.flush()?;
+
+    Ok(saved)
+}
+
+/// Checks a saved table over and adds up to `max_entries` of its entries
+/// to `table`, where a board is in both the deeper entry stays. The file
+/// is read twice, checked whole before anything is added. Returns how
+/// many entries were added.
+pub fn read_table<R: Read + Seek>(
+    table: &HashTable,
+    input: R,
+    max_entries: usize,
+) -> Result<usize, String> {
+    let failed = |err: std::io::Error| format!("Could not read the table: {}", err);
+
+    let mut input = BufReader::new(input);
+    let size = input.seek(SeekFrom::End(0)).map_err(failed)? as usize;
+    input.seek(SeekFrom::Start(0)).map_err(failed)?;
+
+    let mut header = [0; HEADER_SIZE];
+
+    if size < HEADER_SIZE + TRAILER_SIZE
+        || input.read_exact(&mut header).is_err()
+        || &header[0..4] != MAGIC
+    {
+        return Err("Not a saved table".to_string());
+    }
+
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+
+    if version != TABLE_VERSION {
+        return Err(format!(
+            "Table is version {}, this build reads version {}",
+            version, TABLE_VERSION
+        ));
+    }
+
+    let body = size - HEADER_SIZE - TRAILER_SIZE;
+
+    if !body.is_multiple_of(ENTRY_SIZE) {
+        return Err(format!("Table is {} bytes, it was cut short", size));
+    }
+
+    let count = body / ENTRY_SIZE;
+    let mut hash = fnv(FNV_OFFSET, &header);
+    let mut bytes = [0; ENTRY_SIZE];
+
+    for _ in 0..count {
+        input.read_exact(&mut bytes).map_err(failed)?;
+        hash = fnv(hash, &bytes);
+        read_entry(&bytes).ok_or("Table has a bad board")?;
+    }
+
+    let mut trailer = [0; TRAILER_SIZE];
+    input.read_exact(&mut trailer).map_err(failed)?;
+    hash = fnv(hash, &trailer[..8]);
+
+    let saved = u64::from_le_bytes(trailer[0..8].try_into().unwrap()) as usize;
+
+    if saved != count {
+        return Err(format!(
+            "Table should hold {} entries but has {}, it was cut short",
+            saved, count
+        ));
+    }
+
+    if hash != u64::from_le_bytes(trailer[8..16].try_into().unwrap()) {
+        return Err("Table checksum doesn't match, the file is damaged".to_string());
+    }
+
+    input.seek(SeekFrom::Start(HEADER_SIZE as u64)).map_err(failed)?;
+    let mut added = 0;
+
+    for _ in 0..count.min(max_entries) {
+        input.read_exact(&mut bytes).map_err(failed)?;
+
+        // Checked on the first pass
+        let (board, entry) = read_entry(&bytes).unwrap();
+        let deeper = table.get(&board).is_none_or(|old| old.depth <= entry.depth);
+
+        if deeper {
+            table.insert(board, entry);
+            added += 1;
+        }
+    }
+
+    Ok(added)
+}
+
+/// Saves up to `max_entries` of the table to `path`, going through a
+/// temporary file so a save cut short never replaces a good one
+pub fn save_table(table: &HashTable, path: &Path, max_entries: usize) -> Result<usize, String> {
+    let temp = path.with_extension("tmp");
+    let failed = |err: std::io::Error| format!("Could not save table {}: {}", path.display(), err);
+
+    let file = File::create(&temp).map_err(failed)?;
+    let saved = write_table(table, BufWriter::new(file), max_entries).map_err(failed)?;
+    std::fs::rename(&temp, path).map_err(failed)?;
+
+    Ok(saved)
+}
+
+pub fn load_table(table: &HashTable, path: &Path, max_entries: usize) -> Result<usize, String> {
+    let file = File::open(path)
+        .map_err(|err| format!("Could not read table {}: {}", path.display(), err))?;
+
+    read_table(table, file, max_entries).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(all(test, not(feature = "small-board")))]
+mod tests {
+    use super::*;
+    use crate::board::searcher::{find_best_move_list, new_hash_table};
+    use crate::board::{board_from_str, GameState};
+    use crate::config::TEST_BOARD;
+    use std::io::Cursor;
+
+    fn saved(table: &HashTable, max_entries: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_table(table, &mut out, max_entries).unwrap();
+        out
+    }
+
+    fn read(table: &HashTable, data: &[u8], max_entries: usize) -> Result<usize, String> {
+        read_table(table, Cursor::new(data), max_entries)
+    }
+
+    /// A table searched with the water pumped, so the entries carry a tide
+    fn searched() -> (HashTable, GameState) {
+        let mut game = board_from_str(TEST_BOARD, 3);
+        game.tide = Tide {
+            rise: 0,
+            pump_score: 300,
+        };
+
+        let table = new_hash_table(1000);
+        find_best_move_list(&game, 3, false, &table);
+        (table, game)
+    }
+
+    #[test]
+    fn test_pack() {
+        let mut board = board_from_str(TEST_BOARD, 3).board;
+        board[0] = CLEARED;
+        board[71] = JELLYFISH;
+
+        assert_eq!(unpack(&pack(&board).unwrap()), Some(board));
+
+        board[5] = NULL;
+        assert_eq!(pack(&board), None);
+    }
+
+    #[test]
+    fn test_table_round_trip() {
+        let (table, game) = searched();
+        let first = find_best_move_list(&game, 3, false, &table);
+
+        let data = saved(&table, usize::MAX);
+        assert_eq!(data.len(), HEADER_SIZE + table.len() * ENTRY_SIZE + TRAILER_SIZE);
+
+        let loaded = new_hash_table(1000);
+        assert_eq!(read(&loaded, &data, usize::MAX), Ok(table.len()));
+        assert_eq!(loaded.len(), table.len());
+
+        for item in table.iter() {
+            let entry = loaded.get(item.key()).unwrap();
+            assert_eq!(entry.score, item.value().score);
+            assert_eq!(entry.depth, item.value().depth);
+            assert_eq!(entry.water_level, item.value().water_level);
+            assert_eq!(entry.tide, game.tide);
+        }
+
+        // A warm table finds the same move, from the table this time
+        let again = find_best_move_list(&game, 3, false, &loaded);
+        assert_eq!(again.turns[0].turn, first.turns[0].turn);
+        assert!(again.hash_hits > 0);
+
+        // Under another tide the loaded entries don't answer anything
+        let mut still = game;
+        still.tide = Tide::default();
+        assert_eq!(
+            find_best_move_list(&still, 3, false, &loaded).hash_hits,
+            find_best_move_list(&still, 3, false, &new_hash_table(1000)).hash_hits
+        );
+    }
+
+    #[test]
+    fn test_entry_limits() {
+        let (table, _) = searched();
+        let deepest = table.iter().map(|item| item.value().depth).max().unwrap();
+        let at_deepest = table.iter().filter(|item| item.value().depth == deepest).count();
+        assert!(at_deepest < table.len());
+
+        // Saving fewer than the table holds keeps the deepest entries
+        let data = saved(&table, at_deepest);
+        assert_eq!(data.len(), HEADER_SIZE + at_deepest * ENTRY_SIZE + TRAILER_SIZE);
+
+        let loaded = new_hash_table(1000);
+        assert_eq!(read(&loaded, &data, usize::MAX), Ok(at_deepest));
+        assert!(loaded.iter().all(|item| item.value().depth == deepest));
+
+        // Loading stops at the limit too
+        let limited = new_hash_table(1000);
+        assert_eq!(read(&limited, &saved(&table, usize::MAX), 10), Ok(10));
+        assert_eq!(limited.len(), 10);
+
+        assert_eq!(depth_cutoff(&[0; 256], 5), (0, 0));
+    }
+
+    #[test]
+    fn test_damaged_tables() {
+        let (table, _) = searched();
+        let data = saved(&table, usize::MAX);
+        let fresh = new_hash_table(1000);
+
+        let mut flipped = data.clone();
+        flipped[HEADER_SIZE + 3] ^= 1;
+        assert!(read(&fresh, &flipped, usize::MAX).is_err());
+
+        assert!(read(&fresh, &data[..data.len() - 1], usize::MAX).is_err());
+
+        let mut miscounted = data.clone();
+        let count_at = data.len() - TRAILER_SIZE;
+        miscounted[count_at] ^= 1;
+        assert!(read(&fresh, &miscounted, usize::MAX).is_err());
+
+        let mut old = data.clone();
+        old[4] = 1;
+        assert!(read(&fresh, &old, usize::MAX).is_err());
+
+        assert!(read(&fresh, b"nonsense", usize::MAX).is_err());
+        assert!(fresh.is_empty());
+    }
+}
@@ -0,0 +1,576 @@
+//! Slow but plain implementation of the game rules, written for clarity
+//! rather than speed. The real engine in `transforms.rs` and
+//! `transforms_beta.rs` is checked against it by the tests at the bottom.
+//!
+//! Rows are `cells[y][x]` with `y = 0` at the bottom of the board array.
+//! Pieces float up, so after a clear every column is packed towards the top
+//! row and the emptied cells are left at the bottom.
+//!
+//! The engine is built for one board size at compile time, `WIDTH` by
+//! `HEIGHT` from `defs.rs`, with its masks and tables derived from it. The
+//! reference rules work on any size, so other match-3 layouts can be tried
+//! here and boards small enough to enumerate can be tested exhaustively.
+//! `RefBoard` on its own is the engine's size and converts to and from
+//! `GameState`.
+
+use crate::board::defs::*;
+use crate::board::rules::IllegalMove;
+use crate::board::scoring::{ComboKind, SwapScore, SCORES};
+use crate::board::GameState;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RefBoard<const W: usize = WIDTH, const H: usize = HEIGHT> {
+    pub cells: [[Pieces; W]; H],
+    pub water_level: u8,
+}
+
+fn is_normal(piece: Pieces) -> bool {
+    piece <= WAVY_SQUARE
+}
+
+/// Marks the 3x3 around a pufferfish at `(x, y)`
+fn explode<const W: usize, const H: usize>(marks: &mut [[bool; W]; H], x: usize, y: usize) {
+    for row in marks.iter_mut().take((y + 1).min(H - 1) + 1).skip(y.saturating_sub(1)) {
+        for cell in row.iter_mut().take((x + 1).min(W - 1) + 1).skip(x.saturating_sub(1)) {
+            *cell = true;
+        }
+    }
+}
+
+impl RefBoard {
+    pub fn from_state(game: &GameState) -> RefBoard {
+        let mut cells = [[CLEARED; WIDTH]; HEIGHT];
+
+        for (pos, piece) in game.board.iter().enumerate() {
+            cells[pos / WIDTH][pos % WIDTH] = *piece;
+        }
+
+        RefBoard {
+            cells,
+            water_level: game.water_level,
+        }
+    }
+
+    pub fn to_array(&self) -> [Pieces; BOARD_SIZE] {
+        let mut board = [CLEARED; BOARD_SIZE];
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                board[y * WIDTH + x] = self.cells[y][x];
+            }
+        }
+
+        board
+    }
+}
+
+impl<const W: usize, const H: usize> RefBoard<W, H> {
+    pub fn from_cells(cells: [[Pieces; W]; H], water_level: u8) -> RefBoard<W, H> {
+        RefBoard { cells, water_level }
+    }
+
+    /// Why `(x, y)` can't be swapped with its right hand neighbour, if it can't
+    pub fn check_swap(&self, x: usize, y: usize) -> Result<(), IllegalMove> {
+        if y >= H || x >= W {
+            return Err(IllegalMove::Null);
+        }
+
+        if x == W - 1 {
+            return Err(IllegalMove::EdgeColumn);
+        }
+
+        let left = self.cells[y][x];
+        let right = self.cells[y][x + 1];
+
+        if left == NULL || right == NULL {
+            return Err(IllegalMove::Null);
+        }
+        if left == CLEARED || right == CLEARED {
+            return Err(IllegalMove::Cleared);
+        }
+        if left == CRAB || right == CRAB {
+            return Err(IllegalMove::Crab);
+        }
+
+        // Two pufferfish are the only identical pair worth swapping
+        if left == right && left != PUFFERFISH {
+            return Err(IllegalMove::Identical);
+        }
+
+        Ok(())
+    }
+
+    /// Every swap of a cell with its right hand neighbour which the engine allows
+    pub fn legal_moves(&self) -> Vec<(usize, usize)> {
+        let mut moves = Vec::new();
+
+        for y in 0..H {
+            for x in 0..W - 1 {
+                if self.check_swap(x, y).is_ok() {
+                    moves.push((x, y));
+                }
+            }
+        }
+
+        moves
+    }
+
+    /// Swaps `(x, y)` with `(x + 1, y)` and resolves everything that follows,
+    /// returning the score in the same units as `GameState::swap`
+    pub fn swap(&mut self, x: usize, y: usize) -> Result<SwapScore, IllegalMove> {
+        self.check_swap(x, y)?;
+
+        let left = self.cells[y][x];
+        let right = self.cells[y][x + 1];
+
+        let mut score;
+        let kind;
+
+        if left == PUFFERFISH && right == PUFFERFISH {
+            let mut marks = [[false; W]; H];
+            explode(&mut marks, x, y);
+            explode(&mut marks, x + 1, y);
+
+            kind = ComboKind::Pufferfish;
+            score = SCORES.cleared(kind, self.clear_marked(&marks));
+            self.gravity();
+        } else if (left == JELLYFISH && right == PUFFERFISH) || (left == PUFFERFISH && right == JELLYFISH) {
+            // Every pufferfish on the board goes off at once
+            let mut marks = [[false; W]; H];
+
+            for py in 0..H {
+                for px in 0..W {
+                    if self.cells[py][px] == PUFFERFISH {
+                        explode(&mut marks, px, py);
+                    }
+                }
+            }
+
+            kind = ComboKind::Jellyfish;
+            score = SCORES.cleared(kind, self.clear_marked(&marks));
+            self.gravity();
+        } else if left == PUFFERFISH || right == PUFFERFISH {
+            let puffer_x = if left == PUFFERFISH { x } else { x + 1 };
+            let mut marks = [[false; W]; H];
+            explode(&mut marks, puffer_x, y);
+
+            kind = ComboKind::Pufferfish;
+            score = SCORES.cleared(kind, self.clear_marked(&marks));
+            self.gravity();
+        } else if left == JELLYFISH || right == JELLYFISH {
+            // The jellyfish clears every piece like the other one and stays put
+            let target = if left == JELLYFISH { right } else { left };
+            kind = ComboKind::Jellyfish;
+            score = SCORES.cleared(kind, self.clear_all_of(target));
+            self.gravity();
+        } else {
+            self.cells[y][x] = right;
+            self.cells[y][x + 1] = left;
+
+            let (combo, combo_kind) = self.swap_combo(x, y);
+            score = combo as f32;
+            kind = combo_kind;
+
+            if score == 0.0 {
+                return Ok(SwapScore { score, combo: kind });
+            }
+        }
+
+        score += self.cascade();
+        Ok(SwapScore { score, combo: kind })
+    }
+
+    fn clear_marked(&mut self, marks: &[[bool; W]; H]) -> usize {
+        let mut cleared = 0;
+
+        for (row, marked) in self.cells.iter_mut().zip(marks.iter()) {
+            for (cell, marked) in row.iter_mut().zip(marked.iter()) {
+                if *marked {
+                    *cell = CLEARED;
+                    cleared += 1;
+                }
+            }
+        }
+
+        cleared
+    }
+
+    fn clear_all_of(&mut self, target: Pieces) -> usize {
+        let mut cleared = 0;
+
+        for row in self.cells.iter_mut() {
+            for cell in row.iter_mut() {
+                if *cell == target {
+                    *cell = CLEARED;
+                    cleared += 1;
+                }
+            }
+        }
+
+        cleared
+    }
+
+    /// Length of the vertical run through `(x, y)`, looking at most two
+    /// cells each way as the swap scoring does
+    fn column_run(&self, x: usize, y: usize) -> i32 {
+        let piece = self.cells[y][x];
+        let mut run = 1;
+
+        for step in 1..=2 {
+            if y < step || self.cells[y - step][x] != piece {
+                break;
+            }
+            run += 1;
+        }
+
+        for step in 1..=2 {
+            if y + step >= H || self.cells[y + step][x] != piece {
+                break;
+            }
+            run += 1;
+        }
+
+        run
+    }
+
+    /// Score of the lines made by the two swapped pieces, see `get_combo`
+    fn swap_combo(&self, x: usize, y: usize) -> (i32, ComboKind) {
+        let left_piece = self.cells[y][x];
+        let right_piece = self.cells[y][x + 1];
+
+        let left = if x >= 2 && self.cells[y][x - 1] == left_piece && self.cells[y][x - 2] == left_piece {
+            3
+        } else {
+            0
+        };
+
+        let right = if x + 3 < W
+            && self.cells[y][x + 2] == right_piece
+            && self.cells[y][x + 3] == right_piece
+        {
+            3
+        } else {
+            0
+        };
+
+        let keep_line = |run: i32| if run >= 3 { run } else { 0 };
+        let l_col = keep_line(self.column_run(x, y));
+        let r_col = keep_line(self.column_run(x + 1, y));
+
+        let lines = [left, right, l_col, r_col].iter().filter(|run| **run > 0).count() as i32;
+
+        let kind = match lines {
+            0 => return (0, ComboKind::Nothing),
+            1 => ComboKind::Single,
+            2 => ComboKind::Bingo,
+            3 => ComboKind::Donkey,
+            _ if l_col == 5 && r_col == 5 => ComboKind::Vegas,
+            _ => ComboKind::SeaDonkey,
+        };
+
+        let line_score = |run: i32| SCORES.line_score(run as usize);
+
+        let score = (line_score(left) + line_score(right) + line_score(l_col) + line_score(r_col)) * SCORES.combo(kind);
+        (score, kind)
+    }
+
+    /// Marks every run of three or more and every crab above the water,
+    /// returning the marks and the crab bonus
+    fn find_clears(&self) -> ([[bool; W]; H], f32) {
+        let mut marks = [[false; W]; H];
+        let mut bonus = 0.0;
+
+        for y in 0..H {
+            for x in 0..W {
+                let piece = self.cells[y][x];
+
+                if piece == CRAB && y > self.water_level as usize {
+                    marks[y][x] = true;
+                    bonus += self.water_level as f32 * 2.0;
+                }
+
+                if !is_normal(piece) {
+                    continue;
+                }
+
+                if x + 2 < W && self.cells[y][x + 1] == piece && self.cells[y][x + 2] == piece {
+                    marks[y][x] = true;
+                    marks[y][x + 1] = true;
+                    marks[y][x + 2] = true;
+                }
+
+                if y + 2 < H && self.cells[y + 1][x] == piece && self.cells[y + 2][x] == piece {
+                    marks[y][x] = true;
+                    marks[y + 1][x] = true;
+                    marks[y + 2][x] = true;
+                }
+            }
+        }
+
+        (marks, bonus)
+    }
+
+    /// Runs of three or more of the same marked piece in one direction
+    fn marked_lines(&self, marks: &[[bool; W]; H]) -> Vec<Vec<(usize, usize)>> {
+        let rows = (0..H).map(|y| (0..W).map(|x| (x, y)).collect::<Vec<_>>());
+        let columns = (0..W).map(|x| (0..H).map(|y| (x, y)).collect::<Vec<_>>());
+
+        let mut lines = Vec::new();
+
+        for cells in rows.chain(columns) {
+            let mut run: Vec<(usize, usize)> = Vec::new();
+
+            for (x, y) in cells {
+                let piece = self.cells[y][x];
+                let continues = run
+                    .last()
+                    .map(|(lx, ly)| self.cells[*ly][*lx] == piece)
+                    .unwrap_or(false);
+
+                if !(marks[y][x] && is_normal(piece) && continues) {
+                    if run.len() >= 3 {
+                        lines.push(run.clone());
+                    }
+                    run.clear();
+                }
+
+                if marks[y][x] && is_normal(piece) {
+                    run.push((x, y));
+                }
+            }
+
+            if run.len() >= 3 {
+                lines.push(run);
+            }
+        }
+
+        lines
+    }
+
+    /// Lines which share a cell are one group, each group scores the sum of
+    /// its line scores times the multiplier for its number of lines
+    fn score_marks(&self, marks: &[[bool; W]; H]) -> i32 {
+        let lines = self.marked_lines(marks);
+        let mut group: Vec<usize> = (0..lines.len()).collect();
+
+        for i in 0..lines.len() {
+            for j in 0..i {
+                let touching = lines[i].iter().any(|cell| lines[j].contains(cell));
+
+                if touching && group[i] != group[j] {
+                    let (from, to) = (group[j], group[i]);
+                    group.iter_mut().filter(|g| **g == from).for_each(|g| *g = to);
+                }
+            }
+        }
+
+        let mut total = 0;
+
+        for label in 0..lines.len() {
+            let members: Vec<&Vec<(usize, usize)>> = lines
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| group[*i] == label)
+                .map(|(_, line)| line)
+                .collect();
+
+            if members.is_empty() {
+                continue;
+            }
+
+            let line_total: i32 = members.iter().map(|line| SCORES.line_score(line.len())).sum();
+            total += line_total * SCORES.cascade_group(members.len());
+        }
+
+        total
+    }
+
+    /// Clears and refloats until the board is still, returning the score
+    pub fn cascade(&mut self) -> f32 {
+        let mut score = 0.0;
+
+        loop {
+            let (marks, bonus) = self.find_clears();
+            let count = marks.iter().flatten().filter(|mark| **mark).count();
+
+            if count == 0 {
+                return score;
+            }
+
+            score += self.score_marks(&marks) as f32 + bonus;
+
+            for (row, marked) in self.cells.iter_mut().zip(marks.iter()) {
+                for (cell, mark) in row.iter_mut().zip(marked.iter()) {
+                    if *mark {
+                        *cell = CLEARED;
+                    }
+                }
+            }
+
+            self.gravity();
+        }
+    }
+
+    /// Packs each column towards the top keeping the order of the pieces
+    pub fn gravity(&mut self) {
+        for x in 0..W {
+            let pieces: Vec<Pieces> = (0..H)
+                .rev()
+                .map(|y| self.cells[y][x])
+                .filter(|piece| *piece != CLEARED)
+                .collect();
+
+            for y in 0..H {
+                self.cells[y][x] = CLEARED;
+            }
+
+            for (i, piece) in pieces.into_iter().enumerate() {
+                self.cells[H - 1 - i][x] = piece;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{board_from_array, generate_settled_board};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn check_board(game: &GameState) {
+        let reference = RefBoard::from_state(game);
+
+        let engine_moves: Vec<usize> = game.get_moves().into_iter().collect();
+        let reference_moves: Vec<usize> = reference
+            .legal_moves()
+            .into_iter()
+            .map(|(x, y)| y * WIDTH + x)
+            .collect();
+
+        assert_eq!(engine_moves, reference_moves);
+
+        for pos in engine_moves {
+            let mut engine = *game;
+            let engine_score = engine.swap(pos);
+
+            let mut expected = reference;
+            let expected_score = expected.swap(pos % WIDTH, pos / WIDTH);
+
+            if engine_score != expected_score || engine.board != expected.to_array() {
+                game.draw_highlight(pos);
+                panic!(
+                    "swap {} scored {:?} but the reference scored {:?}\nengine    {:?}\nreference {:?}",
+                    pos,
+                    engine_score,
+                    expected_score,
+                    &engine.board[..],
+                    &expected.to_array()[..]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_swap_matches_reference() {
+        let mut rng = StdRng::seed_from_u64(32);
+
+        for seed in 0..1_000u32 {
+            let game = generate_settled_board(&mut rng, (seed % 6) as u8);
+            check_board(&game);
+        }
+    }
+
+    #[test]
+    fn test_gravity_keeps_order() {
+        let mut board = [CLEARED; BOARD_SIZE];
+        board[0] = GREEN_SQUARE;
+        board[2 * WIDTH] = BLUE_CIRCLE;
+        board[5 * WIDTH] = CRAB;
+
+        let mut reference = RefBoard::from_state(&board_from_array(board));
+        reference.gravity();
+
+        assert_eq!(reference.cells[HEIGHT - 1][0], CRAB);
+        assert_eq!(reference.cells[HEIGHT - 2][0], BLUE_CIRCLE);
+        assert_eq!(reference.cells[HEIGHT - 3][0], GREEN_SQUARE);
+        assert_eq!(reference.cells[HEIGHT - 4][0], CLEARED);
+    }
+
+    #[test]
+    fn test_illegal_swaps() {
+        let mut board = [BLUE_CIRCLE; BOARD_SIZE];
+        board[1] = CLEARED;
+        board[4] = CRAB;
+
+        let reference = RefBoard::from_state(&board_from_array(board));
+
+        let swap = |x: usize| {
+            let mut copy = reference;
+            copy.swap(x, 0)
+        };
+
+        assert_eq!(swap(0), Err(IllegalMove::Cleared));
+        assert_eq!(swap(2), Err(IllegalMove::Identical));
+        assert_eq!(swap(3), Err(IllegalMove::Crab));
+        assert_eq!(swap(LAST_COLUMN), Err(IllegalMove::EdgeColumn));
+
+        // The engine turns down the same moves for the same reasons
+        let game = board_from_array(board);
+        for x in 0..WIDTH {
+            assert_eq!(game.validate_swap(x), reference.check_swap(x, 0));
+        }
+    }
+
+    fn packed<const W: usize, const H: usize>(board: &RefBoard<W, H>) -> bool {
+        (0..W).all(|x| (1..H).all(|y| board.cells[y][x] != CLEARED || board.cells[y - 1][x] == CLEARED))
+    }
+
+    /// Every 3x3 board of three kinds of piece and pufferfish, small enough
+    /// to check every move of every board
+    #[test]
+    fn test_small_boards_exhaustively() {
+        const KINDS: [Pieces; 4] = [BLUE_PENTAGON, GREEN_SQUARE, BLUE_CIRCLE, PUFFERFISH];
+
+        let mut checked = 0;
+
+        for code in 0..KINDS.len().pow(9) {
+            let mut cells = [[CLEARED; 3]; 3];
+            let mut rest = code;
+
+            for cell in cells.iter_mut().flatten() {
+                *cell = KINDS[rest % KINDS.len()];
+                rest /= KINDS.len();
+            }
+
+            let board = RefBoard::from_cells(cells, 0);
+
+            for (x, y) in board.legal_moves() {
+                let mut after = board;
+                let SwapScore { score, combo: kind } = after.swap(x, y).unwrap();
+
+                assert!(score >= 0.0);
+
+                if kind == ComboKind::Nothing {
+                    // The pair trades places and nothing else moves
+                    let mut expected = board;
+                    expected.cells[y].swap(x, x + 1);
+
+                    assert_eq!(score, 0.0);
+                    assert_eq!(after, expected);
+                } else {
+                    // Once a swap goes off the board always comes to rest
+                    let (marks, _) = after.find_clears();
+
+                    assert!(score > 0.0);
+                    assert!(!marks.iter().flatten().any(|mark| *mark), "{:?} {:?}", board, (x, y));
+                    assert!(packed(&after), "{:?} {:?}", board, (x, y));
+                }
+
+                checked += 1;
+            }
+        }
+
+        assert!(checked > 1_000_000, "only checked {} moves", checked);
+    }
+}
@@ -0,0 +1,407 @@
+//! Draws a board as an SVG picture for pasting into the wiki or a report,
+//! and as a PNG when built with the `png` feature, which runs the same SVG
+//! through `resvg`.
+//!
+//! Every piece gets its own shape and colour so boards still read in black
+//! and white, the rows under water are shaded blue, the suggested swap is
+//! boxed in yellow and a line of moves can be drawn as arrows, fading the
+//! further down the line they are. Every arrow is drawn on the board given,
+//! so from the second on they mark where a later move is made, not the
+//! pieces it swaps then. The top row is at the top like `draw`.
+//!
+//! Nothing is drawn as text so the PNG comes out the same without fonts.
+
+use crate::board::defs::*;
+use crate::board::rules;
+use crate::board::GameState;
+
+use std::fmt::Write;
+
+/// Colours for every piece kind, up to the jellyfish
+pub const PIECE_COLOURS: [&str; 10] = [
+    "#3b6fd6", "#3aa845", "#5cc8e8", "#2fa38c", "#1f3a8a", "#e4ece6", "#8a5cc7", "#d9482b", "#e8c33a",
+    "#e67ab8",
+];
+
+const BACKGROUND: &str = "#f4efe1";
+const WATER: &str = "#3d7dd8";
+const SWAP: &str = "#ffd400";
+const ARROW: &str = "#e0301e";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    /// Width and height of one cell in pixels
+    pub cell: u32,
+    /// Swap to box, the left hand position like everywhere else
+    pub highlight: Option<usize>,
+    /// Moves to draw as arrows in the order they are played, all of them
+    /// over the starting board
+    pub arrows: Vec<usize>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            cell: 40,
+            highlight: None,
+            arrows: Vec::new(),
+        }
+    }
+}
+
+/// A swap at `pos` and the cell to its right are both on the board, the
+/// boxes and arrows of any other are left out
+fn swap_fits(pos: usize) -> bool {
+    pos < BOARD_SIZE && pos % WIDTH != LAST_COLUMN
+}
+
+/// Top left corner of the cell at `pos`
+fn corner(pos: usize, cell: f32) -> (f32, f32) {
+    let x = (pos % WIDTH) as f32 * cell;
+    let y = (HEIGHT - 1 - pos / WIDTH) as f32 * cell;
+
+    (x, y)
+}
+
+fn polygon(sides: usize, cx: f32, cy: f32, r: f32, turn: f32) -> String {
+    (0..sides)
+        .map(|i| {
+            let angle = turn + i as f32 * std::f32::consts::TAU / sides as f32;
+            format!("{:.1},{:.1}", cx + r * angle.sin(), cy - r * angle.cos())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The shape for one piece centred on `(cx, cy)`, `r` is about the
+/// distance from the centre to the edge of the shape
+fn piece_shape(piece: Pieces, cx: f32, cy: f32, r: f32) -> String {
+    let fill = PIECE_COLOURS[piece as usize];
+    let outline = "stroke='#00000080' stroke-width='1.5'";
+
+    match piece {
+        BLUE_PENTAGON => format!(
+            "<polygon points='{}' fill='{}' {}/>",
+            polygon(5, cx, cy, r, 0.0),
+            fill,
+            outline
+        ),
+        GREEN_SQUARE => format!(
+            "<rect x='{:.1}' y='{:.1}' width='{:.1}' height='{:.1}' fill='{}' {}/>",
+            cx - r * 0.8,
+            cy - r * 0.8,
+            r * 1.6,
+            r * 1.6,
+            fill,
+            outline
+        ),
+        BLUE_CIRCLE => format!(
+            "<circle cx='{:.1}' cy='{:.1}' r='{:.1}' fill='{}' {}/>",
+            cx, cy, r, fill, outline
+        ),
+        BREEN_OCTAGON => format!(
+            "<polygon points='{}' fill='{}' {}/>",
+            polygon(8, cx, cy, r, std::f32::consts::PI / 8.0),
+            fill,
+            outline
+        ),
+        DARK_BLUE_SQUARE => format!(
+            "<polygon points='{}' fill='{}' {}/>",
+            polygon(4, cx, cy, r, 0.0),
+            fill,
+            outline
+        ),
+        PALE_CIRCLE => format!(
+            "<circle cx='{:.1}' cy='{:.1}' r='{:.1}' fill='{}' {}/>\
+             <circle cx='{:.1}' cy='{:.1}' r='{:.1}' fill='none' {}/>",
+            cx,
+            cy,
+            r,
+            fill,
+            outline,
+            cx,
+            cy,
+            r * 0.5,
+            outline
+        ),
+        WAVY_SQUARE => {
+            // Each side bows in and out once
+            let (left, right) = (cx - r * 0.8, cx + r * 0.8);
+            let (top, bottom) = (cy - r * 0.8, cy + r * 0.8);
+            let wave = r * 0.3;
+
+            format!(
+                "<path d='M{:.1},{:.1} Q{:.1},{:.1} {:.1},{:.1} Q{:.1},{:.1} {:.1},{:.1} \
+                 Q{:.1},{:.1} {:.1},{:.1} Q{:.1},{:.1} {:.1},{:.1} Z' fill='{}' {}/>",
+                left,
+                top,
+                cx,
+                top - wave,
+                right,
+                top,
+                right + wave,
+                cy,
+                right,
+                bottom,
+                cx,
+                bottom + wave,
+                left,
+                bottom,
+                left - wave,
+                cy,
+                left,
+                top,
+                fill,
+                outline
+            )
+        }
+        CRAB => format!(
+            "<ellipse cx='{:.1}' cy='{:.1}' rx='{:.1}' ry='{:.1}' fill='{}' {}/>\
+             <circle cx='{:.1}' cy='{:.1}' r='{:.1}' fill='{}' {}/>\
+             <circle cx='{:.1}' cy='{:.1}' r='{:.1}' fill='{}' {}/>",
+            cx,
+            cy + r * 0.2,
+            r * 0.75,
+            r * 0.5,
+            fill,
+            outline,
+            cx - r * 0.7,
+            cy - r * 0.5,
+            r * 0.3,
+            fill,
+            outline,
+            cx + r * 0.7,
+            cy - r * 0.5,
+            r * 0.3,
+            fill,
+            outline
+        ),
+        PUFFERFISH => format!(
+            "<polygon points='{}' fill='{}' {}/><circle cx='{:.1}' cy='{:.1}' r='{:.1}' fill='{}' {}/>",
+            polygon(12, cx, cy, r, 0.0),
+            fill,
+            outline,
+            cx,
+            cy,
+            r * 0.75,
+            fill,
+            outline
+        ),
+        JELLYFISH => format!(
+            "<path d='M{:.1},{:.1} A{:.1},{:.1} 0 0 1 {:.1},{:.1} Z' fill='{}' {}/>\
+             <path d='M{:.1},{:.1} v{:.1} M{:.1},{:.1} v{:.1} M{:.1},{:.1} v{:.1}' stroke='{}' stroke-width='2.5'/>",
+            cx - r,
+            cy,
+            r,
+            r * 0.8,
+            cx + r,
+            cy,
+            fill,
+            outline,
+            cx - r * 0.5,
+            cy,
+            r * 0.9,
+            cx,
+            cy,
+            r * 0.9,
+            cx + r * 0.5,
+            cy,
+            r * 0.9,
+            fill
+        ),
+        _ => String::new(),
+    }
+}
+
+/// The board as an SVG document
+pub fn render_svg(game: &GameState, options: &RenderOptions) -> String {
+    let cell = options.cell as f32;
+    let (width, height) = (cell * WIDTH as f32, cell * HEIGHT as f32);
+    let mut svg = String::new();
+
+    let _ = write!(
+        svg,
+        "<svg xmlns='http://www.w3.org/2000/svg' width='{w}' height='{h}' viewBox='0 0 {w} {h}'>",
+        w = width,
+        h = height
+    );
+    let _ = write!(
+        svg,
+        "<defs><marker id='head' viewBox='0 0 10 10' refX='8' refY='5' markerWidth='4' \
+         markerHeight='4' orient='auto-start-reverse'><path d='M0,0 L10,5 L0,10 Z' fill='{}'/></marker></defs>",
+        ARROW
+    );
+    let _ = write!(
+        svg,
+        "<rect width='{}' height='{}' fill='{}'/>",
+        width, height, BACKGROUND
+    );
+
+    // Every row up to and including the water level is under water
+    let rows_under = (0..HEIGHT).filter(|y| rules::underwater(*y, game.water_level)).count() as f32;
+    if rows_under > 0.0 {
+        let top = height - rows_under * cell;
+        let _ = write!(
+            svg,
+            "<rect class='water' y='{:.1}' width='{}' height='{:.1}' fill='{}' fill-opacity='0.3'/>\
+             <line x1='0' y1='{:.1}' x2='{}' y2='{:.1}' stroke='{}' stroke-width='2'/>",
+            top,
+            width,
+            rows_under * cell,
+            WATER,
+            top,
+            width,
+            top,
+            WATER
+        );
+    }
+
+    for (pos, piece) in game.board.iter().enumerate() {
+        if *piece as usize >= PIECE_COLOURS.len() {
+            continue;
+        }
+
+        let (x, y) = corner(pos, cell);
+        let _ = write!(
+            svg,
+            "<g class='piece p{}'>{}</g>",
+            piece,
+            piece_shape(*piece, x + cell / 2.0, y + cell / 2.0, cell * 0.38)
+        );
+    }
+
+    if let Some(pos) = options.highlight.filter(|pos| swap_fits(*pos)) {
+        let (x, y) = corner(pos, cell);
+        let _ = write!(
+            svg,
+            "<rect class='swap' x='{:.1}' y='{:.1}' width='{:.1}' height='{:.1}' rx='4' \
+             fill='none' stroke='{}' stroke-width='3'/>",
+            x + 1.5,
+            y + 1.5,
+            cell * 2.0 - 3.0,
+            cell - 3.0,
+            SWAP
+        );
+    }
+
+    if options.arrows.len() > 1 {
+        svg.push_str(
+            "<desc>Arrows after the first are later moves of the line drawn over this board</desc>",
+        );
+    }
+
+    for (number, pos) in options.arrows.iter().enumerate() {
+        if !swap_fits(*pos) {
+            continue;
+        }
+
+        let (x, y) = corner(*pos, cell);
+        let opacity = 1.0 - 0.6 * number as f32 / options.arrows.len() as f32;
+        let _ = write!(
+            svg,
+            "<line class='arrow' x1='{:.1}' y1='{:.1}' x2='{:.1}' y2='{:.1}' stroke='{}' \
+             stroke-width='3' stroke-opacity='{:.2}' marker-start='url(#head)' marker-end='url(#head)'/>",
+            x + cell * 0.5,
+            y + cell * 0.5,
+            x + cell * 1.5,
+            y + cell * 0.5,
+            ARROW,
+            opacity
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// The board as a PNG, drawn from the same SVG
+#[cfg(feature = "png")]
+pub fn render_png(game: &GameState, options: &RenderOptions) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let svg = render_svg(game, options);
+    let tree = usvg::Tree::from_str(&svg, &usvg::Options::default())
+        .map_err(|err| format!("Could not read the SVG back: {}", err))?;
+
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or("The board is too small to draw")?;
+
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    pixmap
+        .encode_png()
+        .map_err(|err| format!("Could not encode the PNG: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_from_str;
+    use crate::config::TEST_BOARD;
+
+    #[test]
+    fn test_svg() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let options = RenderOptions {
+            highlight: Some(39),
+            arrows: vec![39, 21, 14],
+            ..RenderOptions::default()
+        };
+
+        let svg = render_svg(&game, &options);
+
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>"));
+        assert!(svg.contains("width='240' height='480'"));
+        assert_eq!(svg.matches("class='piece").count(), 72);
+        assert_eq!(svg.matches("class='swap'").count(), 1);
+        assert_eq!(svg.matches("class='arrow'").count(), 3);
+        assert_eq!(svg.matches("class='water'").count(), 1);
+
+        // One group for each kind of piece on the board
+        for piece in 0..PIECE_COLOURS.len() as Pieces {
+            let count = game.board.iter().filter(|on_board| **on_board == piece).count();
+            assert_eq!(svg.matches(&format!("class='piece p{}'", piece)).count(), count);
+        }
+    }
+
+    #[test]
+    fn test_swaps_off_the_board() {
+        let game = board_from_str(TEST_BOARD, 3);
+
+        // The right hand column and past the top have no pair to draw
+        for pos in [5, 71, 72, 80].iter() {
+            let options = RenderOptions {
+                highlight: Some(*pos),
+                arrows: vec![*pos, 39],
+                ..RenderOptions::default()
+            };
+
+            let svg = render_svg(&game, &options);
+            assert_eq!(svg.matches("class='swap'").count(), 0);
+            assert_eq!(svg.matches("class='arrow'").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_cleared_and_dry_boards() {
+        let mut game = board_from_str(TEST_BOARD, 0);
+        game.board[0] = CLEARED;
+        game.water_level = 0;
+
+        // Row 0 is still under water at level 0, cleared cells draw nothing
+        let svg = render_svg(&game, &RenderOptions::default());
+        assert_eq!(svg.matches("class='piece").count(), 71);
+        assert!(svg.contains("y='440.0' width='240' height='40.0'"));
+        assert!(!svg.contains("class='swap'"));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let png = render_png(&game, &RenderOptions::default()).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+}
@@ -0,0 +1,444 @@
+//! Rules tied to the water line. Row `y` is under water while
+//! `y <= water_level`, everything else is out of it.
+//!
+//! Pieces float up, so a crab only moves when something above it in its
+//! column is cleared and it rises into the gap. Crabs can't be swapped, the
+//! only way to get rid of one is to float it out of the water, at which
+//! point it is cleared along with the lines of that round and scores
+//! `CRAB_SCORE_PER_LEVEL` for every level of water. A crab that is already
+//! out of the water, say once the water has dropped, is cleared on its own
+//! by the next pass of `clean_board`, whether or not anything else clears.
+//!
+//! Special pieces go off when swapped, scoring one per cell they clear:
+//!
+//! * a pufferfish clears the 3x3 around it, two swapped together both go off
+//! * a jellyfish clears every piece like the one it was swapped with and
+//!   stays where it is
+//! * a jellyfish swapped with a pufferfish sets off every pufferfish on the
+//!   board at once
+//! * two jellyfish do nothing, like any other pair of identical pieces
+//!
+//! The water can also move between moves, see `Tide`. It is still unless
+//! asked for, which is how the engine has always played.
+
+use crate::board::defs::*;
+use crate::board::helpers::{x_pos_fast, y_pos_fast};
+use crate::board::GameState;
+
+use std::fmt;
+
+pub const CRAB_SCORE_PER_LEVEL: u8 = 2;
+
+/// Highest the water gets, with the whole board under water
+pub const MAX_WATER_LEVEL: u8 = HEIGHT as u8 - 1;
+
+/// How the water moves between moves. It rises a set amount after every
+/// move and every `pump_score` points the move scored pumps it back down a
+/// level, so a quiet line of play floods the board while big clears keep it
+/// low. A higher line means crabs need more clearing above them but score
+/// more when they get out.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tide {
+    /// Levels the water rises after every move
+    pub rise: u8,
+    /// Points a move has to score to pump the water down a level, 0 never pumps
+    pub pump_score: u16,
+}
+
+impl Tide {
+    #[inline(always)]
+    pub fn is_still(&self) -> bool {
+        self.rise == 0 && self.pump_score == 0
+    }
+
+    /// Water level once a move scoring `score` has been played
+    #[inline]
+    pub fn after_move(&self, water_level: u8, score: f32) -> u8 {
+        let pumped = if self.pump_score == 0 || score <= 0.0 {
+            0
+        } else {
+            (score / self.pump_score as f32).min(MAX_WATER_LEVEL as f32) as u8
+        };
+
+        water_level
+            .saturating_add(self.rise)
+            .saturating_sub(pumped)
+            .min(MAX_WATER_LEVEL)
+    }
+}
+
+#[inline(always)]
+pub fn underwater(y: usize, water_level: u8) -> bool {
+    y <= water_level as usize
+}
+
+/// The crab at `pos` has floated out of the water and gets cleared
+#[inline(always)]
+pub fn crab_escapes(pos: usize, water_level: u8) -> bool {
+    !underwater(y_pos_fast(pos), water_level)
+}
+
+#[inline(always)]
+pub fn crab_score(water_level: u8) -> f32 {
+    // Multiplied as floats, a u8 overflows from water level 128
+    water_level as f32 * CRAB_SCORE_PER_LEVEL as f32
+}
+
+/// Why a swap can't be made
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IllegalMove {
+    /// The rightmost column has nothing to its right to swap with
+    EdgeColumn,
+    /// One of the cells is empty
+    Cleared,
+    /// Both pieces are the same, which only pufferfish get away with
+    Identical,
+    /// Crabs can't be moved, only floated out of the water
+    Crab,
+    /// One of the cells isn't on the board at all
+    Null,
+}
+
+impl fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            IllegalMove::EdgeColumn => "the right hand column can't swap right",
+            IllegalMove::Cleared => "one of the cells is empty",
+            IllegalMove::Identical => "both pieces are the same",
+            IllegalMove::Crab => "crabs can't be swapped",
+            IllegalMove::Null => "one of the cells is off the board",
+        };
+
+        f.write_str(reason)
+    }
+}
+
+/// Checks two neighbouring pieces can be swapped, the edge column aside
+#[inline(always)]
+pub fn check_swap(left: Pieces, right: Pieces) -> Result<(), IllegalMove> {
+    if left == NULL || right == NULL {
+        Err(IllegalMove::Null)
+    } else if left == CLEARED || right == CLEARED {
+        Err(IllegalMove::Cleared)
+    } else if left == CRAB || right == CRAB {
+        Err(IllegalMove::Crab)
+    } else if left == right && left != PUFFERFISH {
+        Err(IllegalMove::Identical)
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether two neighbouring pieces can be swapped, the edge column aside
+#[inline(always)]
+pub fn can_swap(left: Pieces, right: Pieces) -> bool {
+    check_swap(left, right).is_ok()
+}
+
+#[inline(always)]
+pub fn jelly_meets_puffer(left: Pieces, right: Pieces) -> bool {
+    (left == JELLYFISH && right == PUFFERFISH) || (left == PUFFERFISH && right == JELLYFISH)
+}
+
+impl GameState {
+    /// Checks the piece at `pos` can be swapped with the one to its right,
+    /// `get_moves` is every position this accepts
+    #[inline(always)]
+    pub fn validate_swap(&self, pos: usize) -> Result<(), IllegalMove> {
+        if pos >= self.board.len() {
+            return Err(IllegalMove::Null);
+        }
+
+        if x_pos_fast(pos) == LAST_COLUMN {
+            return Err(IllegalMove::EdgeColumn);
+        }
+
+        check_swap(self.board[pos], self.board[pos + 1])
+    }
+
+    /// Positions of every crab on the board, bottom row first
+    pub fn crabs(&self) -> impl Iterator<Item = usize> + '_ {
+        self.board
+            .iter()
+            .enumerate()
+            .filter(|(_, piece)| **piece == CRAB)
+            .map(|(pos, _)| pos)
+    }
+
+    /// Moves the water for a move that scored `score`
+    #[inline(always)]
+    pub fn apply_tide(&mut self, score: f32) {
+        if !self.tide.is_still() {
+            self.water_level = self.tide.after_move(self.water_level, score);
+        }
+    }
+
+    /// How many pieces above the crab at `pos` have to be cleared before it
+    /// floats out of the water, zero if it already has
+    pub fn clears_to_free(&self, pos: usize) -> usize {
+        (self.water_level as usize + 1).saturating_sub(y_pos_fast(pos))
+    }
+}
+
+#[cfg(all(test, not(feature = "small-board")))]
+mod tests {
+    use super::*;
+    use crate::board::scoring::{ComboKind, SwapScore};
+    use crate::board::searcher::{find_best_move_list, new_hash_table, TurnList};
+    use crate::board::{board_from_array, generate_settled_board};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Board with no lines on it which stays that way while a few columns
+    /// shift against each other, as neighbours in a row always differ
+    fn striped_board(water_level: u8) -> GameState {
+        let mut board = [CLEARED; 72];
+
+        for (pos, piece) in board.iter_mut().enumerate() {
+            *piece = ((pos % 6 + 2 * (pos / 6)) % 6) as Pieces;
+        }
+
+        let mut game = board_from_array(board);
+        game.water_level = water_level;
+        game
+    }
+
+    #[test]
+    fn test_crabs_at_every_height() {
+        // Clears needed to free a crab on each row, bottom first, and what
+        // one scores at that water level when it gets out
+        let cases = [
+            (0, [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0.0),
+            (4, [5, 4, 3, 2, 1, 0, 0, 0, 0, 0, 0, 0], 8.0),
+            (11, [12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1], 22.0),
+        ];
+
+        for (water_level, clears, bonus) in cases.iter() {
+            for (y, clears) in clears.iter().enumerate() {
+                let mut game = striped_board(*water_level);
+                game.board[y * 6 + 2] = CRAB;
+
+                let free = *clears == 0;
+                assert_eq!(game.clears_to_free(y * 6 + 2), *clears, "water {} crab {}", water_level, y);
+                assert_eq!(crab_escapes(y * 6 + 2, *water_level), free);
+
+                let score = game.clean_board();
+
+                assert_eq!(game.crabs().count(), !free as usize, "water {} crab {}", water_level, y);
+                assert_eq!(score, if free { *bonus } else { 0.0 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_crab_score() {
+        assert_eq!(crab_score(0), 0.0);
+        assert_eq!(crab_score(3), 6.0);
+        assert_eq!(crab_score(MAX_WATER_LEVEL), 22.0);
+    }
+
+    #[test]
+    fn test_crabs_rise_when_cleared_above() {
+        // Water level, the crab's row and whether rising one gets it out
+        let cases = [
+            (0, 0, true),
+            (4, 3, false),
+            (4, 4, true),
+            (10, 9, false),
+            (10, 10, true),
+            (11, 10, false),
+        ];
+
+        for (water_level, y, escapes) in cases.iter() {
+            let mut game = striped_board(*water_level);
+            game.board[y * 6] = CRAB;
+
+            // A line on the top row, the crab floats up one into its gap
+            game.board[66..69].copy_from_slice(&[WAVY_SQUARE; 3]);
+
+            let score = game.clean_board();
+
+            if *escapes {
+                assert_eq!(game.crabs().count(), 0, "water {} crab {}", water_level, y);
+                assert_eq!(score, 3.0 + 2.0 * *water_level as f32);
+            } else {
+                assert_eq!(game.crabs().collect::<Vec<_>>(), vec![(y + 1) * 6]);
+                assert_eq!(score, 3.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_crabs_cant_be_swapped() {
+        let mut game = striped_board(3);
+        game.board[0] = CRAB;
+
+        assert!(!game.get_moves().contains(&0));
+        assert_eq!(game.swap(0), Err(IllegalMove::Crab));
+        assert_eq!(game.board[0], CRAB);
+    }
+
+    #[test]
+    fn test_validate_swap() {
+        let mut game = striped_board(3);
+        game.board[6] = CLEARED;
+        game.board[12] = CRAB;
+        game.board[18] = NULL;
+        game.board[25] = game.board[24];
+        game.board[30] = PUFFERFISH;
+        game.board[31] = PUFFERFISH;
+
+        assert_eq!(game.validate_swap(0), Ok(()));
+        assert_eq!(game.validate_swap(5), Err(IllegalMove::EdgeColumn));
+        assert_eq!(game.validate_swap(71), Err(IllegalMove::EdgeColumn));
+        assert_eq!(game.validate_swap(72), Err(IllegalMove::Null));
+        assert_eq!(game.validate_swap(6), Err(IllegalMove::Cleared));
+        assert_eq!(game.validate_swap(12), Err(IllegalMove::Crab));
+        assert_eq!(game.validate_swap(18), Err(IllegalMove::Null));
+        assert_eq!(game.validate_swap(24), Err(IllegalMove::Identical));
+        assert_eq!(game.validate_swap(30), Ok(()));
+
+        // get_moves is exactly the positions that pass
+        let valid: Vec<usize> = (0..72).filter(|pos| game.validate_swap(*pos).is_ok()).collect();
+        assert_eq!(game.get_moves().to_vec(), valid);
+    }
+
+    fn cleared(game: &GameState) -> usize {
+        game.board.iter().filter(|piece| **piece == CLEARED).count()
+    }
+
+    #[test]
+    fn test_two_pufferfish_both_go_off() {
+        let mut game = striped_board(3);
+        game.board[32] = PUFFERFISH;
+        game.board[33] = PUFFERFISH;
+
+        assert!(game.get_moves().contains(&32));
+
+        let SwapScore { score, combo } = game.swap(32).unwrap();
+
+        // Two overlapping 3x3 blasts are 4x3 cells
+        assert_eq!(combo, ComboKind::Pufferfish);
+        assert_eq!(score, 12.0);
+        assert_eq!(cleared(&game), 12);
+        assert!(!game.board.contains(&PUFFERFISH));
+    }
+
+    #[test]
+    fn test_jellyfish_sets_off_every_pufferfish() {
+        let mut game = striped_board(3);
+        game.board[32] = JELLYFISH;
+        game.board[33] = PUFFERFISH;
+        game.board[6] = PUFFERFISH;
+        game.board[66] = PUFFERFISH;
+
+        let SwapScore { score, combo } = game.swap(32).unwrap();
+
+        // The blast around 33 takes the jellyfish, the other two are by the edges
+        assert_eq!(combo, ComboKind::Jellyfish);
+        assert_eq!(score, (9 + 6 + 4) as f32);
+        assert_eq!(cleared(&game), 9 + 6 + 4);
+        assert!(!game.board.contains(&PUFFERFISH));
+        assert!(!game.board.contains(&JELLYFISH));
+    }
+
+    #[test]
+    fn test_two_jellyfish_do_nothing() {
+        let mut game = striped_board(3);
+        game.board[32] = JELLYFISH;
+        game.board[33] = JELLYFISH;
+
+        let before = game.board;
+
+        assert!(!game.get_moves().contains(&32));
+        assert_eq!(game.swap(32), Err(IllegalMove::Identical));
+        assert_eq!(game.board, before);
+    }
+
+    #[test]
+    fn test_jellyfish_clears_one_kind() {
+        let mut game = striped_board(3);
+        game.board[32] = JELLYFISH;
+
+        let target = game.board[33];
+        let count = game.board.iter().filter(|piece| **piece == target).count();
+
+        let SwapScore { score, combo } = game.swap(32).unwrap();
+
+        // One of the kind in every row, the jellyfish itself stays
+        assert_eq!(combo, ComboKind::Jellyfish);
+        assert_eq!(count, 12);
+        assert_eq!(score, count as f32);
+        assert_eq!(cleared(&game), count);
+        assert_eq!(game.board.iter().filter(|piece| **piece == JELLYFISH).count(), 1);
+    }
+
+    #[test]
+    fn test_tide() {
+        let still = Tide::default();
+        assert!(still.is_still());
+        assert_eq!(still.after_move(3, 5000.0), 3);
+
+        let tide = Tide {
+            rise: 1,
+            pump_score: 100,
+        };
+
+        assert_eq!(tide.after_move(3, 0.0), 4);
+        assert_eq!(tide.after_move(3, 99.0), 4);
+        assert_eq!(tide.after_move(3, 250.0), 2);
+        assert_eq!(tide.after_move(1, 1000.0), 0);
+        assert_eq!(tide.after_move(MAX_WATER_LEVEL, 0.0), MAX_WATER_LEVEL);
+    }
+
+    #[test]
+    fn test_rising_water_keeps_crabs() {
+        let mut game = striped_board(2);
+        game.board[2 * 6] = CRAB;
+        game.board[66..69].copy_from_slice(&[WAVY_SQUARE; 3]);
+
+        // Still water, the crab floats out of the water and is cleared
+        let mut still = game;
+        still.clean_board();
+        assert_eq!(still.crabs().count(), 0);
+
+        // The water rose a level before the clear, the crab stays under
+        game.tide = Tide {
+            rise: 1,
+            pump_score: 0,
+        };
+        game.apply_tide(0.0);
+        game.clean_board();
+        assert_eq!(game.water_level, 3);
+        assert_eq!(game.crabs().collect::<Vec<_>>(), vec![3 * 6]);
+    }
+
+    #[test]
+    fn test_table_keeps_tides_apart() {
+        let mut rng = StdRng::seed_from_u64(28);
+
+        let scores = |list: TurnList| {
+            let mut scores: Vec<(usize, f32)> =
+                list.turns.iter().map(|info| (info.turn, info.score)).collect();
+            scores.sort_by_key(|(turn, _)| *turn);
+            scores
+        };
+
+        for _ in 0..5 {
+            let still = generate_settled_board(&mut rng, 4);
+            let mut pumped = still;
+            pumped.tide = Tide {
+                rise: 0,
+                pump_score: 1,
+            };
+
+            // A table filled in still water answers nothing for pumped water
+            let shared = new_hash_table(10_000);
+            find_best_move_list(&still, 3, false, &shared);
+
+            let reused = find_best_move_list(&pumped, 3, false, &shared);
+            let fresh = find_best_move_list(&pumped, 3, false, &new_hash_table(10_000));
+            assert_eq!(scores(reused), scores(fresh));
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::board::defs::*;
+use crate::board::helpers::can_move;
+use crate::board::GameState;
+use crate::macros::SafeGetters;
+
+use arrayvec::ArrayVec;
+use serde::Serialize;
+
+/// Every score the engine hands out. A line's length picks its base score,
+/// the lines a swap makes are then multiplied by the entry of the combo they
+/// name, and lines meeting in one group during a cascade by the group's
+/// multiplier. Pufferfish and jellyfish score per piece they clear.
+///
+/// These are the engine's weights, each combo worth far more than the one
+/// below it so the search goes after them, not numbers read off the client.
+pub struct ScoreTable {
+    /// Indexed by line length, anything longer than 6 scores as 6
+    pub line: [i32; 7],
+    pub single: i32,
+    pub bingo: i32,
+    pub donkey: i32,
+    pub sea_donkey: i32,
+    pub vegas: i32,
+    pub pufferfish: i32,
+    pub jellyfish: i32,
+    /// Indexed by how many lines meet in one group during a cascade
+    pub cascade_multiplier: [i32; 5],
+}
+
+pub const SCORES: ScoreTable = ScoreTable {
+    line: [0, 0, 0, 3, 5, 7, 9],
+    single: 1,
+    bingo: 2,
+    donkey: 100,
+    sea_donkey: 10000,
+    vegas: 20000,
+    pufferfish: 1,
+    jellyfish: 1,
+    cascade_multiplier: [0, 1, 2, 3, 4],
+};
+
+/// What a swap made, named as the crew calls them. Lines are counted over
+/// the four the swapped pieces can make, a row and a column each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComboKind {
+    /// Nothing lined up, or the swap wasn't allowed
+    Nothing,
+    /// One line
+    Single,
+    /// Two lines, the 3x3
+    Bingo,
+    /// Three lines
+    Donkey,
+    /// All four lines, both pieces clear a row and a column
+    SeaDonkey,
+    /// A sea donkey with both columns five long, the biggest swap there is
+    Vegas,
+    /// A pufferfish went off
+    Pufferfish,
+    /// A jellyfish cleared every piece of one kind
+    Jellyfish,
+}
+
+impl ComboKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComboKind::Nothing => "nothing",
+            ComboKind::Single => "single",
+            ComboKind::Bingo => "bingo",
+            ComboKind::Donkey => "donkey",
+            ComboKind::SeaDonkey => "sea donkey",
+            ComboKind::Vegas => "vegas",
+            ComboKind::Pufferfish => "pufferfish",
+            ComboKind::Jellyfish => "jellyfish",
+        }
+    }
+}
+
+/// What a legal swap scored, cascades included, and what the swap made
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SwapScore {
+    pub score: f32,
+    pub combo: ComboKind,
+}
+
+/// Names and scores a swap from its four possible lines, `left` and
+/// `right` are the rows made either side and the columns are the vertical
+/// runs through each swapped piece. Lines shorter than three are zero.
+#[inline]
+pub fn swap_combo(left: i32, right: i32, l_col: i32, r_col: i32) -> (i32, ComboKind) {
+    let lines = (left > 0) as usize + (right > 0) as usize + (l_col > 0) as usize + (r_col > 0) as usize;
+
+    let kind = match lines {
+        0 => return (0, ComboKind::Nothing),
+        1 => ComboKind::Single,
+        2 => ComboKind::Bingo,
+        3 => ComboKind::Donkey,
+        _ if l_col == 5 && r_col == 5 => ComboKind::Vegas,
+        _ => ComboKind::SeaDonkey,
+    };
+
+    let score = (row_score!(left) + row_score!(right) + row_score!(l_col) + row_score!(r_col))
+        * SCORES.combo(kind);
+
+    (score, kind)
+}
+
+impl ScoreTable {
+    /// The multiplier on a swap's lines, or per piece cleared for the
+    /// pufferfish and jellyfish
+    #[inline(always)]
+    pub fn combo(&self, kind: ComboKind) -> i32 {
+        match kind {
+            ComboKind::Nothing => 0,
+            ComboKind::Single => self.single,
+            ComboKind::Bingo => self.bingo,
+            ComboKind::Donkey => self.donkey,
+            ComboKind::SeaDonkey => self.sea_donkey,
+            ComboKind::Vegas => self.vegas,
+            ComboKind::Pufferfish => self.pufferfish,
+            ComboKind::Jellyfish => self.jellyfish,
+        }
+    }
+
+    /// Score of `cleared` pieces set off by a pufferfish or jellyfish
+    #[inline(always)]
+    pub fn cleared(&self, kind: ComboKind, cleared: usize) -> f32 {
+        (cleared as i32 * self.combo(kind)) as f32
+    }
+
+    #[inline(always)]
+    pub fn line_score(&self, length: usize) -> i32 {
+        *self.line.get_safely(length.min(6))
+    }
+
+    #[inline(always)]
+    pub fn cascade_group(&self, lines: usize) -> i32 {
+        *self.cascade_multiplier.get_safely(lines.min(4))
+    }
+}
+
+/// A run of three or more of the same piece, as a mask over the 72 cells
+#[derive(Copy, Clone, Debug)]
+pub struct Line {
+    pub cells: u128,
+    pub length: usize,
+}
+
+#[inline(always)]
+fn is_marked(mask: u128, pos: usize) -> bool {
+    mask & (1 << pos) != 0
+}
+
+/// Finds the lines inside `mask`, walking `count` cells from `start` in
+/// steps of `step`
+#[inline]
+fn runs_along(
+    board: &[Pieces; BOARD_SIZE],
+    mask: u128,
+    start: usize,
+    step: usize,
+    count: usize,
+    lines: &mut ArrayVec<Line, MAX_LINES>,
+) {
+    let mut run_start = 0;
+    let mut run_length = 0;
+    let mut run_piece = CLEARED;
+
+    for i in 0..=count {
+        let pos = start + i * step;
+        let piece = if i < count && is_marked(mask, pos) {
+            *board.get_safely(pos)
+        } else {
+            CLEARED
+        };
+
+        if run_length > 0 && piece == run_piece {
+            run_length += 1;
+            continue;
+        }
+
+        if run_length >= 3 && !lines.is_full() {
+            let mut cells = 0;
+            for j in 0..run_length {
+                cells |= 1 << (run_start + j * step);
+            }
+
+            lines.push(Line {
+                cells,
+                length: run_length,
+            });
+        }
+
+        if can_move(piece) {
+            run_start = pos;
+            run_length = 1;
+            run_piece = piece;
+        } else {
+            run_length = 0;
+        }
+    }
+}
+
+/// Most lines a board can hold, every row and column cut into threes
+pub const MAX_LINES: usize = HEIGHT * (WIDTH / 3) + WIDTH * (HEIGHT / 3);
+
+const fn first_column() -> u128 {
+    let mut mask = 0;
+    let mut y = 0;
+
+    while y < HEIGHT {
+        mask |= 1 << (y * WIDTH);
+        y += 1;
+    }
+
+    mask
+}
+
+/// Bits of the cells in column 0 of a clear mask
+const FIRST_COLUMN: u128 = first_column();
+
+/// Bits of the cells in row 0 of a clear mask
+const FIRST_ROW: u128 = (1 << WIDTH) - 1;
+
+/// Every horizontal and vertical line made of cells in `mask`
+pub fn find_lines(board: &[Pieces; BOARD_SIZE], mask: u128) -> ArrayVec<Line, MAX_LINES> {
+    let mut lines = ArrayVec::new();
+
+    for y in 0..HEIGHT {
+        let row = (mask >> (y * WIDTH)) & FIRST_ROW;
+
+        // Only rows with three marked cells in a row can hold a line
+        if row & (row >> 1) & (row >> 2) != 0 {
+            runs_along(board, mask, y * WIDTH, 1, WIDTH, &mut lines);
+        }
+    }
+
+    for x in 0..WIDTH {
+        let column = mask & (FIRST_COLUMN << x);
+
+        // Same again down the column, a row apart is `WIDTH` bits
+        if column & (column >> WIDTH) & (column >> (2 * WIDTH)) != 0 {
+            runs_along(board, mask, x, WIDTH, HEIGHT, &mut lines);
+        }
+    }
+
+    lines
+}
+
+/// Scores one round of a cascade, lines sharing a cell form a group whose
+/// line scores are added up and multiplied by the group's multiplier
+pub fn cascade_score(board: &[Pieces; BOARD_SIZE], mask: u128) -> i32 {
+    group_score(&find_lines(board, mask))
+}
+
+/// Groups lines which share a cell and scores them, any cell layout works as
+/// long as every line uses the same one
+pub fn group_score(lines: &[Line]) -> i32 {
+    let mut used = 0u64;
+    let mut total = 0;
+
+    for first in 0..lines.len() {
+        if used & (1 << first) != 0 {
+            continue;
+        }
+
+        used |= 1 << first;
+
+        let mut group_cells = lines[first].cells;
+        let mut group_score = SCORES.line_score(lines[first].length);
+        let mut group_lines = 1;
+
+        // Keep sweeping until nothing else touches the group
+        let mut grew = true;
+        while grew {
+            grew = false;
+
+            for (other, line) in lines.iter().enumerate().skip(first + 1) {
+                if used & (1 << other) == 0 && line.cells & group_cells != 0 {
+                    used |= 1 << other;
+                    group_cells |= line.cells;
+                    group_score += SCORES.line_score(line.length);
+                    group_lines += 1;
+                    grew = true;
+                }
+            }
+        }
+
+        total += group_score * SCORES.cascade_group(group_lines);
+    }
+
+    total
+}
+
+impl GameState {
+    #[inline(always)]
+    pub fn clear_mask(&self) -> u128 {
+        self.to_clear_l as u128 | ((self.to_clear_r as u128) << 64)
+    }
+
+    /// Score of the lines currently marked to clear, crabs are scored on their own
+    #[inline]
+    pub fn cascade_score(&self) -> f32 {
+        cascade_score(&self.board, self.clear_mask()) as f32
+    }
+}
+
+#[cfg(all(test, not(feature = "small-board")))]
+mod tests {
+    use super::*;
+
+    fn mask_of(cells: &[usize]) -> u128 {
+        cells.iter().fold(0, |mask, pos| mask | 1 << pos)
+    }
+
+    #[test]
+    fn test_line_lengths() {
+        let mut board = [BLUE_CIRCLE; 72];
+        board[3] = GREEN_SQUARE;
+
+        // Three, then four with a different piece breaking the row
+        assert_eq!(cascade_score(&board, mask_of(&[0, 1, 2])), 3);
+        assert_eq!(cascade_score(&board, mask_of(&[0, 1, 2, 3])), 3);
+        assert_eq!(cascade_score(&board, mask_of(&[6, 7, 8, 9])), 5);
+        assert_eq!(cascade_score(&board, mask_of(&[6, 12, 18, 24, 30])), 7);
+
+        // Two marked cells are not a line
+        assert_eq!(cascade_score(&board, mask_of(&[6, 7])), 0);
+
+        // Nor are cells spread down a column, the last column counts too
+        assert_eq!(cascade_score(&board, mask_of(&[5, 11, 23, 29])), 0);
+        assert_eq!(cascade_score(&board, mask_of(&[53, 59, 65, 71])), 5);
+    }
+
+    #[test]
+    fn test_groups() {
+        let board = [BLUE_CIRCLE; 72];
+
+        // An L shape is one group of two lines
+        let l_shape = mask_of(&[6, 7, 8, 12, 18]);
+        assert_eq!(cascade_score(&board, l_shape), (3 + 3) * 2);
+
+        // Two lines apart are two groups
+        let apart = mask_of(&[0, 1, 2, 60, 61, 62]);
+        assert_eq!(cascade_score(&board, apart), 3 + 3);
+
+        // A T shape on the top row
+        let t_shape = mask_of(&[66, 67, 68, 61, 55]);
+        assert_eq!(cascade_score(&board, t_shape), (3 + 3) * 2);
+    }
+
+    #[test]
+    fn test_combo_kinds() {
+        assert_eq!(swap_combo(0, 0, 0, 0), (0, ComboKind::Nothing));
+        assert_eq!(swap_combo(3, 0, 0, 0), (3 * SCORES.single, ComboKind::Single));
+        assert_eq!(swap_combo(3, 0, 4, 0), ((3 + 5) * SCORES.bingo, ComboKind::Bingo));
+        assert_eq!(swap_combo(3, 3, 3, 0), (9 * SCORES.donkey, ComboKind::Donkey));
+
+        // A five long column without all four lines is nothing special
+        assert_eq!(swap_combo(0, 0, 5, 5), (14 * SCORES.bingo, ComboKind::Bingo));
+    }
+
+    #[test]
+    fn test_sea_donkey() {
+        assert_eq!(swap_combo(3, 3, 3, 4), ((9 + 5) * SCORES.sea_donkey, ComboKind::SeaDonkey));
+
+        // One five long column isn't enough for a vegas
+        assert_eq!(swap_combo(3, 3, 5, 4), ((6 + 7 + 5) * SCORES.sea_donkey, ComboKind::SeaDonkey));
+        assert_eq!(swap_combo(3, 3, 4, 5), ((6 + 5 + 7) * SCORES.sea_donkey, ComboKind::SeaDonkey));
+    }
+
+    #[test]
+    fn test_vegas() {
+        assert_eq!(swap_combo(3, 3, 5, 5), ((6 + 14) * SCORES.vegas, ComboKind::Vegas));
+
+        // Scored from its own entry, above the best sea donkey
+        assert_ne!(SCORES.vegas, SCORES.sea_donkey);
+        assert!(swap_combo(3, 3, 5, 5).0 > swap_combo(3, 3, 5, 4).0);
+    }
+}
@@ -2,25 +2,36 @@ use crate::board::GameState;
 
 use crate::board::Board;
 use crate::macros::SafeGetters;
-use atomic_counter::AtomicCounter;
 use dashmap::DashMap;
 use rayon::prelude::*;
 use std::cmp::Ordering;
 use std::intrinsics::likely;
 use std::intrinsics::unlikely;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use super::book::Book;
+use super::defs::{BOARD_SIZE, HEIGHT, LAST_COLUMN, WIDTH};
 use super::helpers::{x_pos_fast, y_pos_fast};
 use ahash::RandomState;
 
-use super::defs::{CLEARED, CRAB, NULL};
+use super::rules;
+use super::scoring::ComboKind;
 
 const DROP_PER_TURN: f32 = 0.9;
 
-#[derive(Debug, Copy, Clone)]
+/// Below anything a legal move can score, so an illegal one is never picked
+const ILLEGAL_SCORE: f32 = -1.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Info {
     pub turn: usize,
     pub score: f32,
+    /// What the move itself makes, before anything later in the line
+    pub combo: ComboKind,
 }
 
 #[derive(Debug)]
@@ -30,53 +41,231 @@ pub struct TurnInfo {
     pub info_str: String,
 }
 
+#[derive(Debug, Clone)]
 pub struct TurnList {
     pub turns: Vec<Info>,
+    pub positions: usize,
+    pub hash_hits: usize,
     pub info_str: String,
 }
 
 pub type HashTable = DashMap<Board, HashEntry, RandomState>;
 
+pub const DEFAULT_TABLE_ENTRIES: usize = 40_000_000;
+
+#[cold]
+pub fn new_hash_table(entries: usize) -> HashTable {
+    DashMap::with_capacity_and_hasher(entries, RandomState::new())
+}
+
+/// Empties the table once it holds more than `max_entries`, for the long
+/// running modes. Run before a search, so the table only goes past the
+/// budget by what one search adds and the search after it starts cold.
+pub fn trim_table(hash_table: &HashTable, max_entries: usize) {
+    if hash_table.len() > max_entries {
+        hash_table.clear();
+    }
+}
+
+/// Rough number of entries which fit in the given number of megabytes
+pub fn entries_for_mb(megabytes: usize) -> usize {
+    (megabytes * 1024 * 1024) / std::mem::size_of::<(Board, HashEntry)>()
+}
+
+/// Searches deeper than this split their moves across the pool
+pub const DEFAULT_PARALLEL_DEPTH: u8 = 3;
+
+/// The rayon pool a search runs on and the depth parallelism starts at.
+/// With no pool of its own the search uses the global rayon pool.
+#[derive(Clone)]
+pub struct SearchPool {
+    pool: Option<Arc<ThreadPool>>,
+    pub parallel_depth: u8,
+    /// Rankings answered without searching, see `book.rs`
+    pub book: Option<Arc<Book>>,
+}
+
+impl Default for SearchPool {
+    fn default() -> SearchPool {
+        SearchPool {
+            pool: None,
+            parallel_depth: DEFAULT_PARALLEL_DEPTH,
+            book: None,
+        }
+    }
+}
+
+impl SearchPool {
+    #[cold]
+    pub fn with_threads(threads: usize, parallel_depth: u8) -> Result<SearchPool, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("bilge-search-{}", i))
+            .build()?;
+
+        Ok(SearchPool {
+            pool: Some(Arc::new(pool)),
+            parallel_depth,
+            book: None,
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        }
+    }
+
+    /// Runs `op` inside the pool so any parallel iterators it uses stay there
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
+
 pub const NULL_MOVE: Info = Info {
     turn: 0,
     score: 0.0,
+    combo: ComboKind::Nothing,
 };
 
+/// What every node of one search shares
+struct SearchCtx<'a> {
+    hasher: &'a HashTable,
+    stop: &'a AtomicBool,
+    /// Sets `stop` once passed, for searches with a time budget
+    deadline: Option<Instant>,
+    parallel_depth: u8,
+}
+
+/// Positions a task searches between looks at the clock
+const DEADLINE_CHECK: usize = 1024;
+
+impl SearchCtx<'_> {
+    #[inline]
+    fn check_deadline(&self) {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.stop.store(true, AtomicOrdering::Relaxed);
+        }
+    }
+}
+
+/// Nodes a search visited and how many the table answered. Each task
+/// keeps its own and they are summed as the tasks finish, a shared
+/// atomic bumped at every node has every thread fighting over it.
+#[derive(Default, Copy, Clone)]
+struct Counts {
+    positions: usize,
+    hash_hits: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.positions += other.positions;
+        self.hash_hits += other.hash_hits;
+    }
+}
+
 #[inline]
 fn search(
     mut copy: GameState,
     depth: u8,
     move_number: usize,
-    cntr: &atomic_counter::RelaxedCounter,
-    hasher: &HashTable,
-    hash_hits: &atomic_counter::RelaxedCounter,
+    ctx: &SearchCtx,
+    counts: &mut Counts,
 ) -> f32 {
-    //cntr.inc();
+    counts.positions += 1;
+    if unlikely(counts.positions.is_multiple_of(DEADLINE_CHECK)) {
+        ctx.check_deadline();
+    }
+
+    if unlikely(ctx.stop.load(AtomicOrdering::Relaxed)) {
+        return 0.0;
+    }
+
     debug_assert!(y_pos_fast(move_number) == y_pos_fast(move_number + 1));
 
-    let mut score = copy.swap(move_number);
+    let score = match copy.swap(move_number) {
+        Ok(swapped) => swapped.score,
+        Err(_) => return ILLEGAL_SCORE,
+    };
+
+    search_swapped(copy, score, depth, move_number, ctx, counts)
+}
+
+/// A root move, searched the same way but keeping what the swap made
+#[inline]
+fn search_root(
+    board: &GameState,
+    depth: u8,
+    move_number: usize,
+    ctx: &SearchCtx,
+    counts: &mut Counts,
+) -> Info {
+    counts.positions += 1;
+    let mut copy = *board;
+
+    match copy.swap(move_number) {
+        Ok(swapped) => Info {
+            turn: move_number,
+            score: search_swapped(copy, swapped.score, depth, move_number, ctx, counts),
+            combo: swapped.combo,
+        },
+        Err(_) => Info {
+            turn: move_number,
+            score: ILLEGAL_SCORE,
+            combo: ComboKind::Nothing,
+        },
+    }
+}
+
+/// The rest of `search` once `move_number` has been played on `copy`,
+/// scoring `score`
+#[inline]
+fn search_swapped(
+    mut copy: GameState,
+    mut score: f32,
+    depth: u8,
+    move_number: usize,
+    ctx: &SearchCtx,
+    counts: &mut Counts,
+) -> f32 {
     let hash_table_range = depth > 1;
 
+    if unlikely(!copy.tide.is_still()) {
+        copy.apply_tide(score);
+    }
+
     if likely(hash_table_range) {
-        let found = hasher.get(&copy.board);
+        let found = ctx.hasher.get(&copy.board);
 
         if let Some(entry) = found {
-            if entry.depth >= depth {
+            // The same board under a different water line or tide plays differently
+            if entry.depth >= depth
+                && entry.water_level == copy.water_level
+                && entry.tide == copy.tide
+            {
+                counts.hash_hits += 1;
                 return entry.score;
             }
         }
     }
 
-    if score < 0.0 || depth == 1 {
+    if depth == 1 {
         return score;
     }
 
     let greater_than_three = depth > 2;
     
+    // Deep searches skip the top and bottom rows, shallow ones keep to the
+    // middle of the board
     let (base, end) = if greater_than_three {
-        (6, 66)
+        (WIDTH, BOARD_SIZE - WIDTH)
     } else {
-        (12usize, 56usize)
+        (2 * WIDTH, (HEIGHT - 3) * WIDTH)
     };
 
     let range = base..end;
@@ -85,22 +274,16 @@ fn search(
         let x_p = x_pos_fast(pos);
 
         let valid_col = if greater_than_three {
-            x_p < 5
+            x_p < LAST_COLUMN
         } else {
-            x_p < 4 && x_p > 1
+            x_p + 2 < WIDTH && x_p > 1
         };
 
         if !valid_col {
             return None;
         }
 
-        let left = *copy.board.get_safely(pos);
-        if left == CLEARED || left == NULL || left == CRAB {
-            return None;
-        }
-
-        let right = *copy.board.get_safely(pos + 1);
-        if right == CLEARED || right == NULL || right == CRAB || right == left {
+        if !rules::can_swap(*copy.board.get_safely(pos), *copy.board.get_safely(pos + 1)) {
             return None;
         }
 
@@ -114,23 +297,48 @@ fn search(
         Some(pos)
     };
 
-    let max_score = if depth > 3 {
-        range.into_par_iter().filter_map(filter)
-            .map(|i| search(copy, depth - 1, i, cntr, hasher, hash_hits))
+    let max_score = if depth > ctx.parallel_depth {
+        let searched: Vec<(f32, Counts)> = range.into_par_iter().filter_map(filter)
+            .map(|i| {
+                // Tasks this small may never reach a clock check of their own
+                ctx.check_deadline();
+
+                let mut task = Counts::default();
+                (search(copy, depth - 1, i, ctx, &mut task), task)
+            })
+            .collect();
+
+        searched.iter().for_each(|(_, task)| counts.add(*task));
+        searched
+            .iter()
+            .map(|(score, _)| *score)
             .max_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal))
             .unwrap_or(0.0)
     } else {
         range
             .filter_map(filter)
-            .map(|i| search(copy, depth - 1, i, cntr, hasher, hash_hits))
+            .map(|i| search(copy, depth - 1, i, ctx, counts))
             .max_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal))
             .unwrap_or(0.0)
     };
 
-    score += max_score as f32 * DROP_PER_TURN;
+    score += max_score * DROP_PER_TURN;
+
+    // A stopped search has only seen some of the moves so can't be cached
+    if unlikely(ctx.stop.load(AtomicOrdering::Relaxed)) {
+        return score;
+    }
 
     if likely(hash_table_range) {
-        hasher.insert(copy.board, HashEntry { score, depth });
+        ctx.hasher.insert(
+            copy.board,
+            HashEntry {
+                score,
+                depth,
+                water_level: copy.water_level,
+                tide: copy.tide,
+            },
+        );
     }
 
     score
@@ -138,30 +346,33 @@ fn search(
 
 #[derive(Copy, Clone)]
 pub struct HashEntry {
-    score: f32,
-    depth: u8,
+    pub score: f32,
+    pub depth: u8,
+    pub water_level: u8,
+    pub tide: rules::Tide,
 }
 
+/// The best move alone, `None` when the board has no moves
 #[inline]
 pub fn find_best_move(
     board: &GameState,
     depth: u8,
     verbose: bool,
     hash_table: &HashTable,
-) -> TurnInfo {
+) -> Option<TurnInfo> {
     let move_list = find_best_move_list(board, depth, verbose, hash_table);
-    let best_move = move_list.turns.get(0).unwrap();
+    let best_move = move_list.turns.first()?;
 
     let info_str = format!(
         "{}, best move {} with score {}",
         move_list.info_str, best_move.turn, best_move.score
     );
 
-    TurnInfo {
+    Some(TurnInfo {
         turn: best_move.turn,
         score: best_move.score,
         info_str,
-    }
+    })
 }
 
 #[inline]
@@ -171,34 +382,163 @@ pub fn find_best_move_list(
     verbose: bool,
     hash_table: &HashTable,
 ) -> TurnList {
+    find_best_move_list_in(board, depth, verbose, hash_table, &SearchPool::default())
+}
+
+/// `find_best_move_list` run on the given pool, ranked from its book
+/// instead when the book has the position
+pub fn find_best_move_list_in(
+    board: &GameState,
+    depth: u8,
+    verbose: bool,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> TurnList {
+    let stop = AtomicBool::new(false);
+    find_best_move_list_with_stop(board, depth, verbose, hash_table, &stop, pool).unwrap()
+}
+
+/// Same as `find_best_move_list` but gives up as soon as `stop` is set,
+/// returning `None` as the scores of a partial search are meaningless
+pub fn find_best_move_list_with_stop(
+    board: &GameState,
+    depth: u8,
+    verbose: bool,
+    hash_table: &HashTable,
+    stop: &AtomicBool,
+    pool: &SearchPool,
+) -> Option<TurnList> {
+    search_moves(board, depth, verbose, hash_table, stop, None, pool)
+}
+
+/// Every root move searched `depth` deep, or ranked by the pool's book when
+/// it went that deep. `None` once `stop` is set or `deadline` passes.
+fn search_moves(
+    board: &GameState,
+    depth: u8,
+    verbose: bool,
+    hash_table: &HashTable,
+    stop: &AtomicBool,
+    deadline: Option<Instant>,
+    pool: &SearchPool,
+) -> Option<TurnList> {
+    if let Some(list) = pool.book.as_ref().and_then(|book| book.probe(board, depth)) {
+        return Some(list);
+    }
+
     let possible_moves = board.get_moves();
-    let cntr = Arc::new(atomic_counter::RelaxedCounter::new(0));
-    let hash_hits = Arc::new(atomic_counter::RelaxedCounter::new(0));
-
-    let mut best_move: Vec<Info> = possible_moves
-        .par_iter()
-        .map(|testing| Info {
-            turn: *testing,
-            score: search(*board, depth, *testing, &cntr, hash_table, &hash_hits),
-        })
-        .collect();
+    let ctx = SearchCtx {
+        hasher: hash_table,
+        stop,
+        deadline,
+        parallel_depth: pool.parallel_depth,
+    };
+
+    let searched: Vec<(Info, Counts)> = pool.install(|| {
+        possible_moves
+            .par_iter()
+            .map(|testing| {
+                let mut task = Counts::default();
+                (search_root(board, depth, *testing, &ctx, &mut task), task)
+            })
+            .collect()
+    });
+
+    let mut counts = Counts::default();
+    searched.iter().for_each(|(_, task)| counts.add(*task));
+    let mut best_move: Vec<Info> = searched.into_iter().map(|(info, _)| info).collect();
+
+    if stop.load(AtomicOrdering::Relaxed) {
+        return None;
+    }
 
     best_move.sort_unstable_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
 
     if verbose {
         println!(
             "Searched {} positions, {} hash hits",
-            cntr.get(),
-            hash_hits.get(),
+            counts.positions, counts.hash_hits,
         );
     }
 
-    TurnList {
+    Some(TurnList {
         turns: best_move,
+        positions: counts.positions,
+        hash_hits: counts.hash_hits,
         info_str: format!(
             "Searched {} positions, {} hash hits.",
-            cntr.get(),
-            hash_hits.get()
+            counts.positions, counts.hash_hits
         ),
+    })
+}
+
+/// Iterative deepening up to `max_depth`, a new depth is only started while
+/// there is more than half of the time budget left as each depth takes
+/// several times longer than the last. A depth still running when the time
+/// is up is abandoned and the last one to finish is returned, depth 1
+/// always runs to the end.
+pub fn find_best_move_timed(
+    board: &GameState,
+    max_depth: u8,
+    time: Duration,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> (TurnList, u8) {
+    let now = Instant::now();
+    let deadline = now + time;
+    let stop = AtomicBool::new(false);
+
+    let mut depth = 1;
+    let mut best = find_best_move_list_in(board, depth, false, hash_table, pool);
+
+    while depth < max_depth && now.elapsed() < time / 2 {
+        match search_moves(board, depth + 1, false, hash_table, &stop, Some(deadline), pool) {
+            Some(list) => {
+                best = list;
+                depth += 1;
+            }
+            None => break,
+        }
+    }
+
+    (best, depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_from_str;
+    use crate::config::TEST_BOARD;
+
+    #[test]
+    fn test_trim_table() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let hash_table = new_hash_table(1024);
+        find_best_move_list(&game, 3, false, &hash_table);
+
+        let filled = hash_table.len();
+        assert!(filled > 0);
+
+        trim_table(&hash_table, filled);
+        assert_eq!(hash_table.len(), filled);
+
+        trim_table(&hash_table, filled - 1);
+        assert_eq!(hash_table.len(), 0);
+    }
+
+    #[test]
+    fn test_timed_stops_mid_depth() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let hash_table = new_hash_table(1 << 16);
+        let pool = SearchPool::default();
+
+        // Depth 12 takes far longer than this, the search has to give up
+        // partway and hand back a depth it finished
+        let started = Instant::now();
+        let (best, depth) = find_best_move_timed(&game, 12, Duration::from_millis(200), &hash_table, &pool);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(depth < 12);
+        assert_eq!(best.turns.len(), game.get_moves().len());
     }
 }
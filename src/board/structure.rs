@@ -1,12 +1,14 @@
 use crate::board::defs::*;
+use crate::board::rules::Tide;
 use std::hash::Hasher;
 
-pub type Board = [Pieces; 6 * 12];
+pub type Board = [Pieces; BOARD_SIZE];
 
 #[derive(Clone, Copy)]
 pub struct GameState {
     pub board: Board,
     pub water_level: u8,
+    pub tide: Tide,
     pub to_clear_l: u64,
     pub to_clear_r: u16,
 }
@@ -18,7 +20,7 @@ pub struct Move {
 }
 
 #[inline(always)]
-pub fn make_hash(brd: &[u8; 72]) -> u64 {
+pub fn make_hash(brd: &[u8; BOARD_SIZE]) -> u64 {
     let mut hashing = ahash::AHasher::default();
 
     hashing.write(brd);
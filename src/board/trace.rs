@@ -0,0 +1,350 @@
+//! Swaps played a step at a time. `swap` only hands back the score, while
+//! `swap_traced` keeps every round of clearing along the way: which cells
+//! went, what the board looked like once the rest floated up, what the
+//! round scored and which special pieces went off. It is for debugging and
+//! animating a move, the search never uses it.
+//!
+//! Cascades are resolved with the full scan `clean_board` rather than the
+//! targeted resolver `swap` uses, the two are checked against each other in
+//! `differential.rs` so the boards and scores come out the same.
+
+use crate::board::defs::*;
+use crate::board::rules::{self, IllegalMove};
+use crate::board::scoring::ComboKind;
+use crate::board::GameState;
+
+/// A special piece going off during a step
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Triggered {
+    /// Cleared the 3x3 around `pos`
+    Pufferfish { pos: usize },
+    /// Cleared every piece like `cleared`, the jellyfish at `pos` stays
+    Jellyfish { pos: usize, cleared: Pieces },
+    /// Floated out of the water at `pos`
+    Crab { pos: usize },
+}
+
+/// One round of clearing
+#[derive(Clone, Debug, PartialEq)]
+pub struct CascadeStep {
+    /// Cells cleared this round, the same split as `GameState`
+    pub to_clear_l: u64,
+    pub to_clear_r: u16,
+    /// The board once everything left has floated up
+    pub board: [Pieces; BOARD_SIZE],
+    /// What the round added to the total, the first round of a normal swap
+    /// includes the swap's own combo score
+    pub score: f32,
+    pub triggered: Vec<Triggered>,
+}
+
+impl CascadeStep {
+    #[inline]
+    pub fn clear_mask(&self) -> u128 {
+        self.to_clear_l as u128 | ((self.to_clear_r as u128) << 64)
+    }
+
+    /// Positions cleared this round, lowest first
+    pub fn cleared(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..BOARD_SIZE).filter(move |pos| self.clear_mask() & (1 << pos) != 0)
+    }
+}
+
+/// Everything a swap did, in order
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapOutcome {
+    pub pos: usize,
+    /// Same as `swap` would score
+    pub score: f32,
+    pub combo: ComboKind,
+    /// The board with the pair traded over and nothing cleared yet. Special
+    /// pieces go off where they are, so for them it is the board before.
+    pub swapped: [Pieces; BOARD_SIZE],
+    /// Empty when the swap made nothing
+    pub steps: Vec<CascadeStep>,
+}
+
+impl SwapOutcome {
+    /// The board once everything has settled
+    pub fn board(&self) -> &[Pieces; BOARD_SIZE] {
+        self.steps.last().map_or(&self.swapped, |step| &step.board)
+    }
+}
+
+impl GameState {
+    /// Same as `swap` but keeps a record of every step
+    pub fn swap_traced(&mut self, pos: usize) -> Result<SwapOutcome, IllegalMove> {
+        self.validate_swap(pos)?;
+        self.reset_clears();
+
+        let one = self.board[pos];
+        let two = self.board[pos + 1];
+
+        let mut triggered = Vec::new();
+
+        let combo = if one == PUFFERFISH && two == PUFFERFISH {
+            triggered.push(Triggered::Pufferfish { pos });
+            triggered.push(Triggered::Pufferfish { pos: pos + 1 });
+
+            self.puff(pos);
+            self.puff(pos + 1);
+            ComboKind::Pufferfish
+        } else if rules::jelly_meets_puffer(one, two) {
+            let jelly = if one == JELLYFISH { pos } else { pos + 1 };
+            triggered.push(Triggered::Jellyfish {
+                pos: jelly,
+                cleared: PUFFERFISH,
+            });
+
+            self.jelly(PUFFERFISH);
+            triggered.extend(self.marked().map(|pos| Triggered::Pufferfish { pos }));
+
+            self.puff_marked();
+            ComboKind::Jellyfish
+        } else if one == PUFFERFISH || two == PUFFERFISH {
+            let puffer = if one == PUFFERFISH { pos } else { pos + 1 };
+            triggered.push(Triggered::Pufferfish { pos: puffer });
+
+            self.puff(puffer);
+            ComboKind::Pufferfish
+        } else if one == JELLYFISH || two == JELLYFISH {
+            let (jelly, target) = if one == JELLYFISH { (pos, two) } else { (pos + 1, one) };
+            triggered.push(Triggered::Jellyfish {
+                pos: jelly,
+                cleared: target,
+            });
+
+            self.jelly(target);
+            ComboKind::Jellyfish
+        } else {
+            return Ok(self.trace_normal(pos));
+        };
+
+        let swapped = self.board;
+        let blast = self.clear_count() as f32;
+
+        let mut steps = vec![self.settle(blast, triggered)];
+        let extra = self.trace_cascade(&mut steps);
+
+        Ok(SwapOutcome {
+            pos,
+            score: blast + extra,
+            combo,
+            swapped,
+            steps,
+        })
+    }
+
+    fn trace_normal(&mut self, pos: usize) -> SwapOutcome {
+        self.board.swap(pos, pos + 1);
+
+        let (combo_score, combo) = self.get_combo(pos);
+        let swapped = self.board;
+        let mut steps = Vec::new();
+
+        let score = if combo_score > 0 {
+            let extra = self.trace_cascade(&mut steps);
+
+            if let Some(first) = steps.first_mut() {
+                first.score += combo_score as f32;
+            }
+
+            combo_score as f32 + extra
+        } else {
+            0.0
+        };
+
+        SwapOutcome {
+            pos,
+            score,
+            combo,
+            swapped,
+            steps,
+        }
+    }
+
+    /// Follows `clean_board` a round at a time, returning the total it
+    /// scores
+    fn trace_cascade(&mut self, steps: &mut Vec<CascadeStep>) -> f32 {
+        let mut extra = 0.0;
+        let mut clear_res = self.mark_clears();
+
+        while clear_res.0 {
+            let score = self.cascade_score() + clear_res.1;
+            let crabs = self
+                .marked()
+                .filter(|pos| self.board[*pos] == CRAB)
+                .map(|pos| Triggered::Crab { pos })
+                .collect();
+
+            extra += score;
+            steps.push(self.settle(score, crabs));
+
+            clear_res = self.mark_clears();
+        }
+
+        extra
+    }
+
+    /// Clears whatever is marked, lets the rest float up and records it
+    fn settle(&mut self, score: f32, triggered: Vec<Triggered>) -> CascadeStep {
+        let (to_clear_l, to_clear_r) = (self.to_clear_l, self.to_clear_r);
+
+        self.remove_clears();
+        self.shift_everything();
+
+        CascadeStep {
+            to_clear_l,
+            to_clear_r,
+            board: self.board,
+            score,
+            triggered,
+        }
+    }
+
+    fn marked(&self) -> impl Iterator<Item = usize> + '_ {
+        let mask = self.clear_mask();
+        (0..BOARD_SIZE).filter(move |pos| mask & (1 << pos) != 0)
+    }
+}
+
+#[cfg(all(test, not(feature = "small-board")))]
+mod tests {
+    use super::*;
+    use crate::board::helpers::can_move;
+    use crate::board::{board_from_array, board_from_str, generate_settled_board};
+    use crate::config::TEST_BOARD;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn check_trace(game: &GameState, pos: usize) {
+        let mut engine = *game;
+        let mut traced = *game;
+
+        let expected = engine.swap(pos).unwrap();
+        let outcome = traced.swap_traced(pos).unwrap();
+
+        assert_eq!(outcome.score, expected.score, "move {}", pos);
+        assert_eq!(outcome.combo, expected.combo, "move {}", pos);
+        assert_eq!(traced.board, engine.board, "move {}", pos);
+        assert_eq!(outcome.board(), &engine.board, "move {}", pos);
+
+        let step_total: f32 = outcome.steps.iter().map(|step| step.score).sum();
+        assert_eq!(step_total, outcome.score, "move {}", pos);
+
+        assert!(outcome.steps.iter().all(|step| step.clear_mask() != 0));
+        assert_eq!(outcome.steps.is_empty(), outcome.score == 0.0);
+    }
+
+    /// First plain swap which clears something
+    fn scoring_move(game: &GameState) -> usize {
+        game.get_moves()
+            .into_iter()
+            .find(|pos| {
+                let mut copy = *game;
+                can_move(game.board[*pos])
+                    && can_move(game.board[pos + 1])
+                    && copy.swap(*pos).unwrap().score > 0.0
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_trace_matches_swap() {
+        let mut rng = StdRng::seed_from_u64(43);
+
+        for seed in 0..300u32 {
+            let game = generate_settled_board(&mut rng, (seed % 6) as u8);
+
+            for pos in game.get_moves() {
+                check_trace(&game, pos);
+            }
+        }
+
+        let game = board_from_str(TEST_BOARD, 3);
+
+        for pos in game.get_moves() {
+            check_trace(&game, pos);
+        }
+    }
+
+    #[test]
+    fn test_trace_steps() {
+        // Striped so nothing lines up, then a row of 0s one swap away on
+        // row 5 with the 1s under it ready to float into a second line
+        let mut board = [0; 72];
+        for (pos, piece) in board.iter_mut().enumerate() {
+            *piece = ((pos % 6 + 2 * (pos / 6)) % 6) as Pieces;
+        }
+        board[30..34].copy_from_slice(&[0, 0, 1, 0]);
+        board[24..27].copy_from_slice(&[2, 1, 1]);
+        board[19] = 3;
+
+        let game = board_from_array(board);
+        let mut traced = game;
+        let outcome = traced.swap_traced(32).unwrap();
+
+        // The pair trades places before anything clears
+        assert_eq!(outcome.swapped[30..34], [0, 0, 0, 1]);
+        assert_eq!(outcome.steps.len(), 2);
+
+        // The 0s go and columns 0 to 2 float up one, 2 1 1 reaching row 5
+        let first = &outcome.steps[0];
+        assert_eq!(first.cleared().collect::<Vec<_>>(), vec![30, 31, 32]);
+        // The line's round and the swap's own combo, 3 each
+        assert_eq!(first.score, 6.0);
+        assert_eq!(first.board[30..34], [2, 1, 1, 1]);
+        assert_eq!(first.board[0..4], [CLEARED, CLEARED, CLEARED, 3]);
+
+        // which makes the 1s a line with the one swapped in
+        let second = &outcome.steps[1];
+        assert_eq!(second.cleared().collect::<Vec<_>>(), vec![31, 32, 33]);
+        assert_eq!(second.score, 3.0);
+        assert_eq!(second.board[30..34], [2, 3, 2, 5]);
+        assert_eq!(second.board[0..6], [CLEARED, CLEARED, CLEARED, CLEARED, 4, 5]);
+
+        assert_eq!(outcome.score, 9.0);
+        assert_eq!(outcome.combo, ComboKind::Single);
+        check_trace(&game, 32);
+        assert_eq!(traced.board, second.board);
+
+        let mut copy = game;
+        assert_eq!(copy.swap_traced(5), Err(IllegalMove::EdgeColumn));
+    }
+
+    #[test]
+    fn test_specials_are_listed() {
+        let mut game = board_from_str(TEST_BOARD, 3);
+        let puffers: Vec<usize> = (0..72).filter(|pos| game.board[*pos] == PUFFERFISH).collect();
+        let puffer = *puffers.iter().find(|pos| *pos % 6 < 5).unwrap();
+
+        game.board[puffer + 1] = JELLYFISH;
+        let puffers: Vec<usize> = (0..72).filter(|pos| game.board[*pos] == PUFFERFISH).collect();
+
+        let outcome = game.swap_traced(puffer).unwrap();
+        let first = &outcome.steps[0];
+
+        assert_eq!(outcome.combo, ComboKind::Jellyfish);
+        assert_eq!(
+            first.triggered[0],
+            Triggered::Jellyfish {
+                pos: puffer + 1,
+                cleared: PUFFERFISH
+            }
+        );
+
+        let set_off: Vec<Triggered> = puffers
+            .iter()
+            .map(|pos| Triggered::Pufferfish { pos: *pos })
+            .collect();
+        assert_eq!(first.triggered[1..], set_off[..]);
+
+        // A crab out of the water goes with the first round of clearing
+        let mut game = board_from_str(TEST_BOARD, 3);
+        game.board[66] = CRAB;
+
+        let pos = scoring_move(&game);
+        let outcome = game.swap_traced(pos).unwrap();
+        assert!(outcome.steps[0].triggered.contains(&Triggered::Crab { pos: 66 }));
+    }
+}
@@ -4,6 +4,8 @@ use crate::board::*;
 
 use defs::*;
 use helpers::can_move;
+use rules::IllegalMove;
+use scoring::{ComboKind, SwapScore, SCORES};
 use recolored::*;
 
 use std::arch::x86_64 as x86;
@@ -15,7 +17,7 @@ impl GameState {
     pub fn as_dani_string(&self) -> String {
         self.board
             .iter()
-            .map(|pce| (*pce as u8) - 1)
+            .map(|pce| *pce - 1)
             .map(|st| st.to_string())
             .collect()
     }
@@ -29,22 +31,27 @@ impl GameState {
     pub fn draw_highlight(&self, position: usize) {
         println!();
 
-        for y in (0..12).rev() {
-            for x in 0..6 {
-                let pos = (y * 6) + x;
+        for y in (0..HEIGHT).rev() {
+            for x in 0..WIDTH {
+                let pos = (y * WIDTH) + x;
                 let piece = self.board[pos];
 
                 if pos == position || pos == position + 1 {
                     print!("{}", defs::draw_piece(piece).bright_green())
-                } else if x_pos_fast(pos) == 5 {
+                } else if x_pos_fast(pos) == LAST_COLUMN {
                     print!("{}", defs::draw_piece(piece).red())
-                } else if y <= self.water_level as usize {
+                } else if rules::underwater(y, self.water_level) {
                     print!("{}", defs::draw_piece(piece).blue())
                 } else {
                     print!("{}", defs::draw_piece(piece));
                 }
             }
-            println!(" : {}", y);
+
+            if rules::underwater(y, self.water_level) {
+                println!(" : {} {}", y, "~".blue());
+            } else {
+                println!(" : {}", y);
+            }
         }
         println!();
     }
@@ -133,15 +140,30 @@ impl GameState {
         }
     }
 
+    /// Marks every `clearing` piece a cell at a time, for boards too small
+    /// for the SIMD loads
+    #[inline]
+    fn jelly_scalar(&mut self, clearing: u8) {
+        for pos in 0..BOARD_SIZE {
+            if self.board[pos] == clearing {
+                self.set_to_clear(pos);
+            }
+        }
+    }
+
     #[inline]
     pub fn jelly_512(&mut self, clearing: u8) {
+        if BOARD_SIZE < 64 {
+            return self.jelly_scalar(clearing);
+        }
+
         unsafe {
             let clear_mask = x86::_mm512_set1_epi8(clearing as i8);
             let ptr = self.board.as_ptr();
             let x = x86::_mm512_loadu_si512(ptr.cast());
             self.to_clear_l |= x86::_mm512_cmpeq_epi8_mask(x, clear_mask);
 
-            for i in 64..72 {
+            for i in 64..self.board.len() {
                 let checking = self.board[i];
 
                 self.to_clear_r |= ((checking == clearing) as u16) << (i - 64);
@@ -151,19 +173,24 @@ impl GameState {
 
     #[inline]
     pub fn jelly(&mut self, clearing: u8) {
+        if BOARD_SIZE < 64 {
+            return self.jelly_scalar(clearing);
+        }
+
         unsafe {
             let clear_mask = x86::_mm256_set1_epi8(clearing as i8);
             let ptr = self.board.as_ptr();
 
             let x = x86::_mm256_loadu_si256(ptr.cast());
             let res = x86::_mm256_cmpeq_epi8(x, clear_mask);
-            self.to_clear_l |= x86::_mm256_movemask_epi8(res) as u64;
+            // Through u32 so a match in cell 31 doesn't sign extend over the upper half
+            self.to_clear_l |= x86::_mm256_movemask_epi8(res) as u32 as u64;
 
             let x = x86::_mm256_loadu_si256(ptr.add(32).cast());
             let res = x86::_mm256_cmpeq_epi8(x, clear_mask);
-            self.to_clear_l |= (x86::_mm256_movemask_epi8(res) as u64) << 32;
+            self.to_clear_l |= (x86::_mm256_movemask_epi8(res) as u32 as u64) << 32;
 
-            for i in 64..72 {
+            for i in 64..self.board.len() {
                 let checking = self.board[i];
 
                 self.to_clear_r |= ((checking == clearing) as u16) << (i - 64);
@@ -171,28 +198,60 @@ impl GameState {
         }
     }
 
+    /// Marks the 3x3 around the pufferfish at `pos` to clear
     #[inline]
-    fn puff(&mut self, pos: usize) {
+    pub fn puff(&mut self, pos: usize) {
         let pair = *PUFFER.get_safely(pos);
         self.apply_pair_to_self(pair)
     }
 
+    /// Sets off every pufferfish currently marked to clear
     #[inline]
-    pub fn swap(&mut self, pos: usize) -> f32 {
+    pub fn puff_marked(&mut self) {
+        let mut left = self.to_clear_l;
+        let mut right = self.to_clear_r;
+
+        while left != 0 {
+            self.puff(left.trailing_zeros() as usize);
+            left &= left - 1;
+        }
+
+        while right != 0 {
+            self.puff(right.trailing_zeros() as usize + 64);
+            right &= right - 1;
+        }
+    }
+
+    /// Swaps the pair at `pos`, clears everything it sets off and returns
+    /// the score along with what kind of combo the swap itself made. Moves
+    /// `validate_swap` turns down leave the board as it was.
+    #[inline]
+    pub fn swap(&mut self, pos: usize) -> Result<SwapScore, IllegalMove> {
+        self.validate_swap(pos)?;
         self.reset_clears();
-        let something_cleared;
 
         let one = *self.board.get_safely(pos);
         let two = *self.board.get_safely(pos + 1);
 
         let mut return_score: f32;
+        let combo;
+
+        if unlikely(one == PUFFERFISH && two == PUFFERFISH) {
+            self.puff(pos);
+            self.puff(pos + 1);
+
+            combo = ComboKind::Pufferfish;
+            return_score = SCORES.cleared(combo, self.clear_count());
+            self.remove_clears();
+            self.shift_everything();
+        } else if unlikely(rules::jelly_meets_puffer(one, two)) {
+            self.jelly(PUFFERFISH);
+            self.puff_marked();
 
-        if unlikely(one == CLEARED || two == CLEARED) {
-            return -20001.0;
-        } else if unlikely(one == two) {
-            return -30001.0;
-        } else if unlikely(one == CRAB || two == CRAB) {
-            return -9001.0;
+            combo = ComboKind::Jellyfish;
+            return_score = SCORES.cleared(combo, self.clear_count());
+            self.remove_clears();
+            self.shift_everything();
         } else if unlikely(one == PUFFERFISH || two == PUFFERFISH) {
             if one == PUFFERFISH {
                 self.puff(pos);
@@ -200,62 +259,46 @@ impl GameState {
                 self.puff(pos + 1);
             }
 
-            return_score = self.clear_count() as f32;
+            combo = ComboKind::Pufferfish;
+            return_score = SCORES.cleared(combo, self.clear_count());
             self.remove_clears();
             self.shift_everything();
-            something_cleared = true
         } else if unlikely(one == JELLYFISH || two == JELLYFISH) {
             if one == JELLYFISH {
                 self.jelly(two);
             } else {
                 self.jelly(one);
             }
-            return_score = self.clear_count() as f32;
+            combo = ComboKind::Jellyfish;
+            return_score = SCORES.cleared(combo, self.clear_count());
 
             self.remove_clears();
             self.shift_everything();
-            something_cleared = true
         } else {
             *self.board.get_mut_safely(pos) = two;
             *self.board.get_mut_safely(pos + 1) = one;
 
-            let mut score = self.get_combo(pos) as f32;
+            let (combo, kind) = self.get_combo(pos);
+            let mut score = combo as f32;
 
             if score > 0.0 {
                 score += self.clean_board_beta(pos);
             }
-            return score;
+            return Ok(SwapScore { score, combo: kind });
         }
 
-        if unlikely(something_cleared) {
-            return_score += self.clean_board();
-        }
+        return_score += self.clean_board();
 
-        return_score
+        Ok(SwapScore {
+            score: return_score,
+            combo,
+        })
     }
 
     #[inline]
-    pub fn get_moves(&self) -> ArrayVec<usize, 60> {
-        self.board
-            .iter()
-            .enumerate()
-            .filter_map(|(pos, pieces)| {
-                if x_pos_fast(pos) == 5 {
-                    return None;
-                }
-
-                let left = *pieces;
-                if left == CLEARED || left == NULL || left == CRAB {
-                    return None;
-                }
-
-                let right = *self.board.get_safely(pos + 1);
-                if right == CLEARED || right == NULL || right == CRAB || right == left {
-                    return None;
-                }
-
-                Some(pos)
-            })
+    pub fn get_moves(&self) -> ArrayVec<usize, MAX_MOVES> {
+        (0..self.board.len())
+            .filter(|pos| self.validate_swap(*pos).is_ok())
             .collect()
     }
 
@@ -265,7 +308,7 @@ impl GameState {
         let mut clear_res = self.mark_clears();
 
         while clear_res.0 {
-            extra_broken += self.clear_count() as f32;
+            extra_broken += self.cascade_score();
             extra_broken += clear_res.1;
             self.remove_clears();
             self.shift_everything();
@@ -276,7 +319,7 @@ impl GameState {
     }
 
     #[inline]
-    fn mark_clears(&mut self) -> (bool, f32) {
+    pub fn mark_clears(&mut self) -> (bool, f32) {
         let mut returning = false;
         let mut bonus_score = 0.0;
 
@@ -288,12 +331,12 @@ impl GameState {
             let x = x_pos_fast(pos);
             let y = y_pos_fast(pos);
 
-            let board_size = 72;
+            let board_size = BOARD_SIZE;
 
-            if unlikely(y > self.water_level as usize && piece == CRAB) {
+            if unlikely(piece == CRAB && !rules::underwater(y, self.water_level)) {
                 self.set_to_inside(&mut outer_a, &mut outer_b, pos);
                 returning = true;
-                bonus_score += (self.water_level * 2) as f32;
+                bonus_score += rules::crab_score(self.water_level);
 
                 continue;
             }
@@ -302,7 +345,7 @@ impl GameState {
                 continue;
             }
 
-            if x < 4
+            if x + 2 < WIDTH
                 && pos < board_size - 2
                 && piece == *self.board.get_safely(pos + 1)
                 && piece == *self.board.get_safely(pos + 2)
@@ -314,13 +357,13 @@ impl GameState {
                 returning = true;
             }
 
-            if pos < 60
-                && piece == *self.board.get_safely(pos + 6)
-                && piece == *self.board.get_safely(pos + 12)
+            if pos + 2 * WIDTH < BOARD_SIZE
+                && piece == *self.board.get_safely(pos + WIDTH)
+                && piece == *self.board.get_safely(pos + 2 * WIDTH)
             {
                 self.set_to_inside(&mut outer_a, &mut outer_b, pos);
-                self.set_to_inside(&mut outer_a, &mut outer_b, pos + 6);
-                self.set_to_inside(&mut outer_a, &mut outer_b, pos + 12);
+                self.set_to_inside(&mut outer_a, &mut outer_b, pos + WIDTH);
+                self.set_to_inside(&mut outer_a, &mut outer_b, pos + 2 * WIDTH);
 
                 returning = true;
             }
@@ -333,45 +376,34 @@ impl GameState {
     }
 
     #[inline]
-    fn shift_everything(&mut self) {
-        for x in 0..6 {
-            let mut pos = 11;
+    pub fn shift_everything(&mut self) {
+        for x in 0..WIDTH {
+            let mut pos = HEIGHT - 1;
 
-            for i in (0..12).rev() {
-                let writing = (pos * 6) + x;
-                let checking = *self.board.get_mut_safely(((i * 6) + x) as usize);
+            for i in (0..HEIGHT).rev() {
+                let writing = (pos * WIDTH) + x;
+                let checking = *self.board.get_mut_safely((i * WIDTH) + x);
 
                 *self.board.get_mut_safely(writing) = checking;
 
                 let offset = *LUT.get_safely(checking as usize) as usize;
-                pos -= offset;
+                // A column with no gaps steps below row 0 on its last
+                // piece, wrapping takes it past the check below
+                pos = pos.wrapping_sub(offset);
             }
-            match pos {
-                0 => update_all(&mut self.board, x, 0),
-                1 => update_all(&mut self.board, x, 1),
-                2 => update_all(&mut self.board, x, 2),
-                3 => update_all(&mut self.board, x, 3),
-                4 => update_all(&mut self.board, x, 4),
-                5 => update_all(&mut self.board, x, 5),
-                6 => update_all(&mut self.board, x, 6),
-                7 => update_all(&mut self.board, x, 7),
-                8 => update_all(&mut self.board, x, 8),
-                9 => update_all(&mut self.board, x, 9),
-                10 => update_all(&mut self.board, x, 10),
-                _ => {}
+            if pos < HEIGHT - 1 {
+                update_all(&mut self.board, x, pos);
             }
         }
     }
 
     #[inline]
-    fn get_combo(&self, pos: usize) -> i32 {
+    pub fn get_combo(&self, pos: usize) -> (i32, ComboKind) {
         let x = x_pos_fast(pos);
 
         let left_piece = self.board.get_safely(pos);
         let right_piece = self.board.get_safely(pos + 1);
 
-        let mut mult_ct = 0;
-
         let mut left = 0; //left 3 pieces
         let mut l_col = 1; //left column of 5 pieces
         let mut right = 0; //right 3 pieces
@@ -382,41 +414,42 @@ impl GameState {
             && self.board.get_safely(pos - 2) == left_piece
         {
             left = 3;
-            mult_ct = 1;
         }
 
-        if x < 3
+        if x + 3 <= LAST_COLUMN
             && self.board.get_safely(pos + 2) == right_piece
             && self.board.get_safely(pos + 3) == right_piece
         {
             right = 3;
-            mult_ct += 1;
         }
 
-        if pos > 5 && self.board.get_safely(pos - 6) == left_piece {
+        if pos >= WIDTH && self.board.get_safely(pos - WIDTH) == left_piece {
             l_col += 1;
-            if pos > 11 && self.board.get_safely(pos - 12) == left_piece {
+            if pos >= 2 * WIDTH && self.board.get_safely(pos - 2 * WIDTH) == left_piece {
                 l_col += 1;
             }
         }
 
-        if pos < 66 && self.board.get_safely(pos + 6) == left_piece {
+        if pos + WIDTH < BOARD_SIZE && self.board.get_safely(pos + WIDTH) == left_piece {
             l_col += 1;
-            if pos < 60 && self.board.get_safely(pos + 12) == left_piece {
+            if pos + 2 * WIDTH < BOARD_SIZE && self.board.get_safely(pos + 2 * WIDTH) == left_piece
+            {
                 l_col += 1;
             }
         }
 
-        if pos > 4 && self.board.get_safely(pos - 5) == right_piece {
+        if pos + 1 >= WIDTH && self.board.get_safely(pos + 1 - WIDTH) == right_piece {
             r_col += 1;
-            if pos > 10 && self.board.get_safely(pos - 11) == right_piece {
+            if pos + 1 >= 2 * WIDTH && self.board.get_safely(pos + 1 - 2 * WIDTH) == right_piece {
                 r_col += 1;
             }
         }
 
-        if pos < 65 && self.board.get_safely(pos + 7) == right_piece {
+        if pos + 1 + WIDTH < BOARD_SIZE && self.board.get_safely(pos + 1 + WIDTH) == right_piece {
             r_col += 1;
-            if pos < 59 && self.board.get_safely(pos + 13) == right_piece {
+            if pos + 1 + 2 * WIDTH < BOARD_SIZE
+                && self.board.get_safely(pos + 1 + 2 * WIDTH) == right_piece
+            {
                 r_col += 1;
             }
         }
@@ -428,27 +461,6 @@ impl GameState {
             l_col = 0;
         }
 
-        if l_col >= 3 {
-            mult_ct += 1;
-        }
-        if r_col >= 3 {
-            mult_ct += 1;
-        }
-
-        // Nothing broke
-        if mult_ct == 0 {
-            return 0;
-        }
-
-        if mult_ct == 4 && (r_col == 5 && l_col == 5) {
-            return 9999999; // Minus to give space for other points
-        }
-
-        if mult_ct == 4 && (r_col == 5 || l_col == 5) {
-            return 999999; // Minus to give space for other points
-        }
-
-        (row_score!(left) + row_score!(right) + row_score!(l_col) + row_score!(r_col))
-            * promote_scorers!(mult_ct)
+        scoring::swap_combo(left, right, l_col, r_col)
     }
 }
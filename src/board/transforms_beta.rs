@@ -26,9 +26,9 @@ const fn build_lut() -> [u8; 256] {
 pub const LUT: [u8; 256] = build_lut();
 
 #[inline(always)]
-pub fn update_all(board: &mut [u8; 72], x: usize, y: usize) {
+pub fn update_all(board: &mut [u8; BOARD_SIZE], x: usize, y: usize) {
     for i in 0..(y + 1) {
-        let writing = (i * 6) + x;
+        let writing = (i * WIDTH) + x;
         *board.get_mut_safely(writing) = CLEARED;
     }
 }
@@ -36,8 +36,8 @@ pub fn update_all(board: &mut [u8; 72], x: usize, y: usize) {
 impl GameState {
     #[inline]
     pub fn clean_board_beta(&mut self, pos: usize) -> f32 {
-        let mut position_tracker: [isize; 6] = [-1; 6];
-        let mut removing_tracker: [usize; 72] = [0; 72];
+        let mut position_tracker: [isize; WIDTH] = [-1; WIDTH];
+        let mut removing_tracker: [usize; BOARD_SIZE] = [0; BOARD_SIZE];
         let mut removing_count: usize = 2;
 
         removing_tracker[0] = pos;
@@ -47,7 +47,7 @@ impl GameState {
         let mut clear_res = self.mark_clears_targetted(&mut removing_count, &mut removing_tracker);
 
         while clear_res.0 {
-            extra_broken += clear_res.1 + self.clear_count() as f32;
+            extra_broken += clear_res.1 + self.cascade_score();
 
             self.remove_clears_max(&mut position_tracker);
             self.simple_tracker(
@@ -64,87 +64,43 @@ impl GameState {
 
     pub fn simple_tracker(
         &mut self,
-        position_tracker: &mut [isize; 6],
+        position_tracker: &mut [isize; WIDTH],
         removing_count: &mut usize,
-        removing_tracker: &mut [usize; 72],
+        removing_tracker: &mut [usize; BOARD_SIZE],
     ) {
         for (x, max_y) in position_tracker.iter().enumerate() {
             if *max_y < 0 {
                 continue;
             }
 
-            let mut pos = 11;
+            let mut pos = HEIGHT - 1;
             let mut flag = 0;
 
-            for i in (0..12).rev() {
-                let writing = (pos * 6) + x;
-                let checking = *self.board.get_mut_safely(((i * 6) + x) as usize);
+            for i in (0..HEIGHT).rev() {
+                let writing = (pos * WIDTH) + x;
+                let checking = *self.board.get_mut_safely((i * WIDTH) + x);
 
                 *self.board.get_mut_safely(writing) = checking;
 
                 let offset = *LUT.get_safely(checking as usize) as usize;
-                pos -= offset;
+                // A column with no gaps steps below row 0 on its last
+                // piece, wrapping takes it past the check below
+                pos = pos.wrapping_sub(offset);
 
                 flag |= 1 - offset;
-                *removing_tracker.get_mut_safely(*removing_count as usize) = writing;
+                *removing_tracker.get_mut_safely(*removing_count) = writing;
                 *removing_count += flag & offset;
             }
 
-            match pos {
-                0 => update_all(&mut self.board, x, 0),
-                1 => update_all(&mut self.board, x, 1),
-                2 => update_all(&mut self.board, x, 2),
-                3 => update_all(&mut self.board, x, 3),
-                4 => update_all(&mut self.board, x, 4),
-                5 => update_all(&mut self.board, x, 5),
-                6 => update_all(&mut self.board, x, 6),
-                7 => update_all(&mut self.board, x, 7),
-                8 => update_all(&mut self.board, x, 8),
-                9 => update_all(&mut self.board, x, 9),
-                10 => update_all(&mut self.board, x, 10),
-                _ => {}
+            if pos < HEIGHT - 1 {
+                update_all(&mut self.board, x, pos);
             }
         }
     }
 
-    #[inline]
-    fn shift_tracked(
-        &mut self,
-        position_tracker: &mut [isize; 6],
-        removing_count: &mut usize,
-        removing_tracker: &mut [usize; 72],
-    ) {
-        
-            for (x, max_y) in position_tracker.iter().enumerate() {
-                let max_y = match max_y {
-                    d if *d < 0 => continue,
-                    a => *a as usize,
-                };
-
-                let mut last = 99999;
-                for y in (0..=max_y).rev() {
-                    let pos = (y * 6) + x;
-                    let checking = *self.board.get_safely(pos);
-                    if checking == CLEARED && last == 99999 {
-                        last = y;
-                    }
-
-                    if last != 99999 && checking != CLEARED {
-                        let last_pos = (last * 6) + x;
-                        *self.board.get_mut_safely(last_pos) = checking;
-                        *self.board.get_mut_safely(pos) = CLEARED;
-
-                        *removing_tracker.get_mut_safely(*removing_count) = last_pos;
-                        *removing_count += 1;
-                        last -= 1;
-                    }
-                }
-        }
-    }
-
     #[inline]
     /// New function which will return the biggest y cleared
-    pub fn remove_clears_max(&mut self, position_tracker: &mut [isize; 6]) {
+    pub fn remove_clears_max(&mut self, position_tracker: &mut [isize; WIDTH]) {
         if self.clear_count() == 0 {
             return;
         }
@@ -167,10 +123,10 @@ impl GameState {
 
     /// Alternative to mark clears which will check around a point
     #[inline]
-    fn mark_clears_targetted(
+    pub fn mark_clears_targetted(
         &mut self,
         removing_count: &mut usize,
-        removing_tracker: &mut [usize; 72],
+        removing_tracker: &mut [usize; BOARD_SIZE],
     ) -> (bool, f32) {
         let mut returning = false;
         let mut bonus_score = 0.0;
@@ -183,11 +139,11 @@ impl GameState {
 
             let y = y_pos_fast(pos);
 
-            if unlikely(piece == CRAB && y > self.water_level as usize) {
+            if unlikely(piece == CRAB && !rules::underwater(y, self.water_level)) {
                 self.set_to_clear(pos);
 
                 returning = true;
-                bonus_score += (self.water_level * 2) as f32;
+                bonus_score += rules::crab_score(self.water_level);
 
                 continue;
             }
@@ -204,10 +160,10 @@ impl GameState {
 
             let x = x_pos_fast(pos);
 
-            if x < 5 && piece == *self.board.get_safely(pos + 1) {
+            if x < LAST_COLUMN && piece == *self.board.get_safely(pos + 1) {
                 x_right_range += 1;
 
-                if x < 4 && piece == *self.board.get_safely(pos + 2) {
+                if x + 1 < LAST_COLUMN && piece == *self.board.get_safely(pos + 2) {
                     x_right_range += 1;
                 }
             }
@@ -220,18 +176,18 @@ impl GameState {
                 }
             }
 
-            if y < 11 && piece == *self.board.get_safely(pos + 6) {
+            if y < HEIGHT - 1 && piece == *self.board.get_safely(pos + WIDTH) {
                 y_up_range += 1;
 
-                if y < 10 && piece == *self.board.get_safely(pos + 12) {
+                if y < HEIGHT - 2 && piece == *self.board.get_safely(pos + 2 * WIDTH) {
                     y_up_range += 1;
                 }
             }
 
-            if y > 0 && piece == *self.board.get_safely(pos - 6) {
+            if y > 0 && piece == *self.board.get_safely(pos - WIDTH) {
                 y_down_range += 1;
 
-                if y > 1 && piece == *self.board.get_safely(pos - 12) {
+                if y > 1 && piece == *self.board.get_safely(pos - 2 * WIDTH) {
                     y_down_range += 1;
                 }
             }
@@ -261,13 +217,13 @@ impl GameState {
 
                 if y_up_range > 0 {
                     for y_range in 1..y_up_range + 1 {
-                        self.set_to_clear(pos + (y_range * 6));
+                        self.set_to_clear(pos + (y_range * WIDTH));
                     }
                 }
 
                 if y_down_range > 0 {
                     for y_range in 1..y_down_range + 1 {
-                        self.set_to_clear(pos - (y_range * 6));
+                        self.set_to_clear(pos - (y_range * WIDTH));
                     }
                 }
             }
@@ -278,14 +234,14 @@ impl GameState {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "small-board")))]
 mod tests {
-    use bilge::board::GameState;
+    use crate::board::GameState;
 
     const C: u8 = 0b0000_1010;
 
     #[rustfmt::skip]
-    const array: [u8; 72] = [
+    const ARRAY: [u8; 72] = [
         8, 8, 8, 8, 8, 8, 
         1, 1, 1, 1, 1, 1, 
         C, C, C, C, C, C, 
@@ -302,7 +258,7 @@ mod tests {
 
     
     #[rustfmt::skip]
-    const array_full: [u8; 72] = [
+    const ARRAY_FULL: [u8; 72] = [
         8, 8, 8, 8, 8, 8, 
         1, 1, 1, 1, 1, 1, 
         3, 4, 4, 4, 4, 4, 
@@ -321,8 +277,9 @@ mod tests {
     #[test]
     fn test_dropping() {
         let mut state = GameState {
-            board: array,
+            board: ARRAY,
             water_level: 0,
+            tide: Default::default(),
             to_clear_l: 0,
             to_clear_r: 0,
         };
@@ -339,11 +296,36 @@ mod tests {
         state.draw();
     }
 
+    #[test]
+    fn test_full_columns_dont_move() {
+        let mut state = GameState {
+            board: ARRAY_FULL,
+            water_level: 0,
+            tide: Default::default(),
+            to_clear_l: 0,
+            to_clear_r: 0,
+        };
+
+        // With nothing cleared the write position steps below the bottom
+        // row on the last piece, which overflowed in debug builds
+        state.shift_everything();
+        assert_eq!(state.board, ARRAY_FULL);
+
+        let mut max = [12, 12, 12, 12, 12, 12];
+        let mut cntr = 0;
+        let mut rm_track = [0; 72];
+
+        state.simple_tracker(&mut max, &mut cntr, &mut rm_track);
+        assert_eq!(state.board, ARRAY_FULL);
+        assert_eq!(cntr, 0);
+    }
+
     #[test]
     fn test_jelly_two() {
         let mut state = GameState {
-            board: array_full,
+            board: ARRAY_FULL,
             water_level: 0,
+            tide: Default::default(),
             to_clear_l: 0,
             to_clear_r: 0,
         };
@@ -360,8 +342,9 @@ mod tests {
     #[test]
     fn test_jelly() {
         let mut state = GameState {
-            board: array,
+            board: ARRAY,
             water_level: 0,
+            tide: Default::default(),
             to_clear_l: 0,
             to_clear_r: 0,
         };
@@ -0,0 +1,392 @@
+//! Reads a board off a PNG screenshot of the bilging puzzle.
+//!
+//! The board's position on screen is given as a `Calibration`: the pixel at
+//! the top left of the top row and the size of one cell. Every cell is then
+//! compared pixel by pixel against a sprite for each piece, and the closest
+//! one wins. Sprites live in a directory as `<piece>.png` with a second set
+//! `<piece>-water.png` of the same pieces under water, since the water
+//! tints everything behind it. Pieces are numbered as in `defs.rs`.
+//!
+//! The water line comes from the background in the corners of each cell,
+//! which is the dry or the wet background colour of the sprites. Rows are
+//! counted up from the bottom until one is dry.
+//!
+//! The sprites in `sprites/` are cut from `render.rs` boards, so out of the
+//! box this reads back our own PNGs. Reading the game client needs sprites
+//! cut from its screenshots, named the same way, and has not been tried
+//! against a real client screenshot yet.
+
+use crate::board::defs::*;
+use crate::board::rules::MAX_WATER_LEVEL;
+use crate::board::{board_from_array, GameState};
+
+use resvg::tiny_skia::Pixmap;
+use std::path::Path;
+
+/// Mean difference per colour channel above which a cell isn't counted as
+/// any sprite at all
+const MAX_DISTANCE: f32 = 24.0;
+
+/// Pixels in from each corner of a cell where the background is sampled
+const CORNER_INSET: u32 = 2;
+
+type Rgb = [u8; 3];
+
+/// Where the board sits in a screenshot, in pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Calibration {
+    /// Top left corner of the leftmost cell of the top row
+    pub origin_x: u32,
+    pub origin_y: u32,
+    pub cell: u32,
+}
+
+impl Calibration {
+    /// Reads `x,y,cell`
+    pub fn parse(input: &str) -> Result<Calibration, String> {
+        let numbers = input
+            .split(',')
+            .map(|part| part.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Calibration should be x,y,cell, got '{}'", input))?;
+
+        let calibration = match numbers[..] {
+            [origin_x, origin_y, cell] if cell > 2 * CORNER_INSET => Calibration {
+                origin_x,
+                origin_y,
+                cell,
+            },
+            [_, _, _] => return Err("The cell size is too small to read".to_string()),
+            _ => return Err(format!("Calibration should be x,y,cell, got '{}'", input)),
+        };
+
+        calibration.extent()?;
+        Ok(calibration)
+    }
+
+    /// The pixel just past the board's bottom right corner, an error when
+    /// that is past the largest image there could be
+    fn extent(&self) -> Result<(u32, u32), String> {
+        let right = self.cell.checked_mul(WIDTH as u32).and_then(|width| width.checked_add(self.origin_x));
+        let bottom = self.cell.checked_mul(HEIGHT as u32).and_then(|height| height.checked_add(self.origin_y));
+
+        right
+            .zip(bottom)
+            .ok_or_else(|| "The calibration puts the board off any screenshot".to_string())
+    }
+
+    /// Top left pixel of the cell at `pos`, inside `extent` so it can't
+    /// overflow once that has been checked
+    fn corner(&self, pos: usize) -> (u32, u32) {
+        let x = (pos % WIDTH) as u32;
+        let y = (HEIGHT - 1 - pos / WIDTH) as u32;
+
+        (self.origin_x + x * self.cell, self.origin_y + y * self.cell)
+    }
+}
+
+/// An opaque RGB image
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn from_png(data: &[u8]) -> Result<Image, String> {
+        let pixmap = Pixmap::decode_png(data).map_err(|err| format!("Not a PNG we can read: {}", err))?;
+
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .map(|pixel| {
+                let pixel = pixel.demultiply();
+                [pixel.red(), pixel.green(), pixel.blue()]
+            })
+            .collect();
+
+        Ok(Image {
+            width: pixmap.width(),
+            height: pixmap.height(),
+            pixels,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Image, String> {
+        let data = std::fs::read(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        Image::from_png(&data).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    #[inline]
+    fn at(&self, x: u32, y: u32) -> Rgb {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Average colour of the `size` square with its top left at `(x, y)`
+    fn average(&self, x: u32, y: u32, size: u32) -> [f32; 3] {
+        let mut total = [0.0; 3];
+
+        for dy in 0..size {
+            for dx in 0..size {
+                let pixel = self.at(x + dx, y + dy);
+                for (sum, value) in total.iter_mut().zip(pixel.iter()) {
+                    *sum += *value as f32;
+                }
+            }
+        }
+
+        total.map(|sum| sum / (size * size) as f32)
+    }
+
+    /// Background colour of the `cell` square at `(x, y)`, from its corners
+    fn background(&self, x: u32, y: u32, cell: u32) -> [f32; 3] {
+        let far = cell - CORNER_INSET - 2;
+        let corners = [
+            (CORNER_INSET, CORNER_INSET),
+            (far, CORNER_INSET),
+            (CORNER_INSET, far),
+            (far, far),
+        ];
+
+        let mut total = [0.0; 3];
+        for (dx, dy) in corners.iter() {
+            let corner = self.average(x + dx, y + dy, 2);
+            for (sum, value) in total.iter_mut().zip(corner.iter()) {
+                *sum += value / 4.0;
+            }
+        }
+
+        total
+    }
+}
+
+fn colour_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|channel| (a[channel] - b[channel]).abs()).sum::<f32>() / 3.0
+}
+
+/// One set of sprites, either dry or under water, with the background
+/// colour they sit on
+struct SpriteSet {
+    sprites: Vec<(Pieces, Image)>,
+    background: [f32; 3],
+}
+
+impl SpriteSet {
+    fn load(dir: &Path, suffix: &str) -> Result<SpriteSet, String> {
+        let sprites = (0..=JELLYFISH)
+            .map(|piece| Ok((piece, Image::load(&dir.join(format!("{}{}.png", piece, suffix)))?)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let (_, first) = &sprites[0];
+        let background = first.background(0, 0, first.width.min(first.height));
+
+        Ok(SpriteSet { sprites, background })
+    }
+
+    /// Closest piece to the cell at `(x, y)` and how far off it was.
+    /// Sprites of a different size are scaled to the cell.
+    fn classify(&self, shot: &Image, x: u32, y: u32, cell: u32) -> (Pieces, f32) {
+        // An empty cell is nothing but background
+        let mut best = (CLEARED, self.empty_distance(shot, x, y, cell));
+
+        for (piece, sprite) in self.sprites.iter() {
+            let mut total = 0.0;
+
+            for dy in 0..cell {
+                for dx in 0..cell {
+                    let from = sprite.at(dx * sprite.width / cell, dy * sprite.height / cell);
+                    let pixel = shot.at(x + dx, y + dy);
+
+                    total += (0..3)
+                        .map(|channel| (from[channel] as f32 - pixel[channel] as f32).abs())
+                        .sum::<f32>();
+                }
+            }
+
+            let distance = total / (cell * cell * 3) as f32;
+
+            if distance < best.1 {
+                best = (*piece, distance);
+            }
+        }
+
+        best
+    }
+
+    fn empty_distance(&self, shot: &Image, x: u32, y: u32, cell: u32) -> f32 {
+        let mut total = 0.0;
+
+        for dy in 0..cell {
+            for dx in 0..cell {
+                let pixel = shot.at(x + dx, y + dy);
+                total += colour_distance(pixel.map(|value| value as f32), self.background);
+            }
+        }
+
+        total / (cell * cell) as f32
+    }
+}
+
+/// The dry and wet sprites for every piece
+pub struct Sprites {
+    dry: SpriteSet,
+    wet: SpriteSet,
+}
+
+impl Sprites {
+    pub fn load(dir: &Path) -> Result<Sprites, String> {
+        Ok(Sprites {
+            dry: SpriteSet::load(dir, "")?,
+            wet: SpriteSet::load(dir, "-water")?,
+        })
+    }
+
+    /// Reads the board and water line out of a screenshot
+    pub fn read_board(&self, shot: &Image, calibration: &Calibration) -> Result<GameState, String> {
+        let cell = calibration.cell;
+        let (right, bottom) = calibration.extent()?;
+
+        if right > shot.width || bottom > shot.height {
+            return Err(format!(
+                "The calibration puts the board outside the {}x{} screenshot",
+                shot.width, shot.height
+            ));
+        }
+
+        let water_level = self.water_level(shot, calibration);
+        let mut board = [CLEARED; BOARD_SIZE];
+        let mut unknown = Vec::new();
+
+        for (pos, piece) in board.iter_mut().enumerate() {
+            let (x, y) = calibration.corner(pos);
+            let sprites = if pos / WIDTH <= water_level as usize {
+                &self.wet
+            } else {
+                &self.dry
+            };
+
+            let (found, distance) = sprites.classify(shot, x, y, cell);
+
+            if distance > MAX_DISTANCE {
+                unknown.push(pos.to_string());
+            }
+
+            *piece = found;
+        }
+
+        if !unknown.is_empty() {
+            return Err(format!(
+                "Cells {} don't look like any sprite, check the calibration",
+                unknown.join(", ")
+            ));
+        }
+
+        let mut game = board_from_array(board);
+        game.water_level = water_level;
+        Ok(game)
+    }
+
+    /// Counts up from the bottom row while rows look wet. Row 0 is always
+    /// under water, even at level 0.
+    fn water_level(&self, shot: &Image, calibration: &Calibration) -> u8 {
+        let wet_row = |y: usize| {
+            let wet_cells = (0..WIDTH)
+                .filter(|x| {
+                    let (left, top) = calibration.corner(y * WIDTH + x);
+                    let background = shot.background(left, top, calibration.cell);
+
+                    colour_distance(background, self.wet.background)
+                        < colour_distance(background, self.dry.background)
+                })
+                .count();
+
+            wet_cells * 2 > WIDTH
+        };
+
+        (1..=MAX_WATER_LEVEL as usize).take_while(|y| wet_row(*y)).count() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_from_str;
+    use crate::config::TEST_BOARD;
+
+    /// The screenshots here are `render`'s own PNGs set into a larger frame,
+    /// not captures of the game client, so they only show the reading works
+    /// on the shipped sprites
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+    const SPRITES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/sprites");
+
+    fn read_fixture(name: &str, calibration: &str) -> Result<GameState, String> {
+        let sprites = Sprites::load(Path::new(SPRITES))?;
+        let shot = Image::load(&Path::new(FIXTURES).join(name))?;
+
+        sprites.read_board(&shot, &Calibration::parse(calibration)?)
+    }
+
+    /// The board in `screenshot_water_7.png`, a settled board with some
+    /// gaps, a crab and a jellyfish
+    fn gappy_board() -> [Pieces; 72] {
+        let mut board =
+            board_from_str("056130336006220114462630365503606120124403122066243480110054161465400533", 7).board;
+
+        board[0] = CLEARED;
+        board[1] = CLEARED;
+        board[6] = CLEARED;
+        board[40] = CRAB;
+        board[41] = JELLYFISH;
+        board
+    }
+
+    #[test]
+    fn test_calibration() {
+        assert_eq!(
+            Calibration::parse("37, 52,40"),
+            Ok(Calibration {
+                origin_x: 37,
+                origin_y: 52,
+                cell: 40
+            })
+        );
+
+        assert!(Calibration::parse("37,52").is_err());
+        assert!(Calibration::parse("37,52,forty").is_err());
+        assert!(Calibration::parse("37,52,3").is_err());
+        assert!(Calibration::parse("4294967295,0,40").is_err());
+        assert!(Calibration::parse("0,4294967295,40").is_err());
+        assert!(Calibration::parse("0,0,1000000000").is_err());
+    }
+
+    #[test]
+    fn test_read_screenshots() {
+        let game = read_fixture("screenshot_water_3.png", "37,52,40").unwrap();
+        assert_eq!(game.board, board_from_str(TEST_BOARD, 3).board);
+        assert_eq!(game.water_level, 3);
+
+        // Noisier, further into the corner and under more water
+        let game = read_fixture("screenshot_water_7.png", "11,23,40").unwrap();
+        assert_eq!(game.board, gappy_board());
+        assert_eq!(game.water_level, 7);
+    }
+
+    #[test]
+    fn test_bad_calibration() {
+        // Off by half a cell nothing matches
+        assert!(read_fixture("screenshot_water_3.png", "57,52,40").is_err());
+        // Past the edge of the screenshot
+        assert!(read_fixture("screenshot_water_3.png", "300,52,40").is_err());
+
+        // Off the end of a u32 rather than only the screenshot
+        let huge = Calibration {
+            origin_x: u32::MAX,
+            origin_y: 0,
+            cell: 40,
+        };
+        let sprites = Sprites::load(Path::new(SPRITES)).unwrap();
+        let shot = Image::load(&Path::new(FIXTURES).join("screenshot_water_3.png")).unwrap();
+        assert!(sprites.read_board(&shot, &huge).is_err());
+    }
+}
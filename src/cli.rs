@@ -0,0 +1,665 @@
+use crate::board::book::Book;
+use crate::board::defs::BOARD_SIZE;
+use crate::board::rules::{Tide, MAX_WATER_LEVEL};
+use crate::board::searcher::{
+    entries_for_mb, new_hash_table, HashTable, SearchPool, DEFAULT_PARALLEL_DEPTH,
+    DEFAULT_TABLE_ENTRIES,
+};
+use crate::board::{board_from_str, GameState};
+use crate::output::OutputFormat;
+
+use crate::board::animate::DEFAULT_FRAME_TIME;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: bilgebot <command> [options]
+
+Commands:
+    solve <board>       Find the best move for a board, a digit per cell
+    serve               Run the engine protocol over stdin (alias stdin),
+                        with --port answer HTTP requests instead
+    bench [corpus]      Time a search of every board in a corpus file,
+                        the built in corpus without one
+    draw <board>        Print a board, --move highlights a swap
+    render <board>      Draw a board to an SVG, or a PNG when built with
+                        the png feature, --move boxes a swap
+    read <screenshot>   Read a board off a PNG screenshot, needs the png
+                        feature and --calibration
+    simulate [board]    Play the engine against random refills, --record
+                        saves the game
+    replay <record>     Step through a recorded game, searching every
+                        position at --depth to flag mistakes
+    book build [corpus] Search every board of a corpus at --depth and save
+                        every move's score to the --out book
+    analyze <board>     List every move ranked by score
+    analyze <record>    Rank every move of a recorded game against the
+                        search at --depth and sum up the combos missed
+
+Options:
+    --depth <n>         Search depth (default 6)
+    --water <n>         Water level (default 3)
+    --rise <n>          Levels the water rises after every move (default 0)
+    --pump <n>          Points a move needs to pump the water down a level,
+                        0 never pumps (default 0)
+    --threads <n>       Number of search threads (default all cores)
+    --parallel-depth <n> Split the search across threads above this depth
+                        (default 3)
+    --hash-mb <n>       Transposition table size in megabytes
+    --time <ms>         Deepen until this many milliseconds are used
+    --book <file>       Rank positions in this book without searching them,
+                        for every command which searches
+    --table-file <file> Load the hash table from this file when serve
+                        starts and save it there when it stops
+    --format <fmt>      Output format, text or json (default text)
+    --move <n>          Move to highlight in draw
+    --animate           Play the move in draw step by step, cascades and all
+    --frame-ms <n>      Milliseconds each step of --animate stays up
+                        (default 700)
+    --out <file>        File for render to write, .svg or .png, or the
+                        book for book build
+    --pv <n>            Arrows in render for the engine's next n moves
+    --calibration <x,y,cell> Where read finds the board, the top left
+                        pixel of row 11 and the cell size in pixels
+    --sprites <dir>     Piece sprites for read (default sprites)
+    --turns <n>         Number of moves to simulate (default 20)
+    --record <file>     File for simulate to save the game to
+    --port <n>          Port for serve to listen on, localhost only
+    --depths <list>     Comma separated depths for bench (default 6)
+    --runs <n>          Searches of each board in bench, the fastest
+                        counts (default 3)
+    --baseline <file>   Compare bench against a saved baseline, exits
+                        with 1 if a move changed or a board got slower
+    --save-baseline <file> Save the bench results as a baseline
+    --threshold <n>     Percent slower a board may get (default 10)
+
+Running with <board> <depth> <water> is the same as solve, with no
+arguments it runs bench.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Solve { board: String },
+    Serve { port: Option<u16> },
+    Bench(BenchOptions),
+    Draw {
+        board: String,
+        highlight: Option<usize>,
+        /// Time each frame stays up when animating the move
+        animate: Option<Duration>,
+    },
+    Render {
+        board: String,
+        out: String,
+        highlight: Option<usize>,
+        /// Number of moves of the engine's line to draw as arrows
+        pv: usize,
+    },
+    Read {
+        screenshot: String,
+        /// `x,y,cell`, checked when the screenshot is read
+        calibration: String,
+        sprites: String,
+    },
+    Simulate {
+        board: Option<String>,
+        turns: usize,
+        record: Option<String>,
+    },
+    Replay { record: String },
+    BuildBook {
+        /// Corpus file, the built in corpus when empty
+        corpus: Option<String>,
+        out: String,
+    },
+    Analyze { board: String },
+    AnalyzeRecord { record: String },
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    /// Corpus file, the built in corpus when empty
+    pub corpus: Option<String>,
+    /// Depths to search every board at, `--depth` when empty
+    pub depths: Vec<u8>,
+    pub runs: usize,
+    pub baseline: Option<String>,
+    pub save_baseline: Option<String>,
+    pub threshold: f64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> BenchOptions {
+        BenchOptions {
+            corpus: None,
+            depths: Vec::new(),
+            runs: 3,
+            baseline: None,
+            save_baseline: None,
+            threshold: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub depth: u8,
+    pub water: u8,
+    pub threads: Option<usize>,
+    pub parallel_depth: u8,
+    pub hash_mb: Option<usize>,
+    pub time: Option<Duration>,
+    pub format: OutputFormat,
+    pub tide: Tide,
+    pub book: Option<String>,
+    pub table_file: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            depth: 6,
+            water: 3,
+            threads: None,
+            parallel_depth: DEFAULT_PARALLEL_DEPTH,
+            hash_mb: None,
+            time: None,
+            format: OutputFormat::Text,
+            tide: Tide::default(),
+            book: None,
+            table_file: None,
+        }
+    }
+}
+
+impl Options {
+    /// The board at the chosen water level, with the water moving as asked
+    pub fn game(&self, board: &str) -> GameState {
+        let mut game = board_from_str(board, self.water);
+        game.tide = self.tide;
+        game
+    }
+
+    /// A dedicated pool when `--threads` was given, otherwise the global one,
+    /// with the `--book` loaded
+    pub fn search_pool(&self) -> Result<SearchPool, String> {
+        let mut pool = match self.threads {
+            Some(threads) => SearchPool::with_threads(threads, self.parallel_depth)
+                .map_err(|err| format!("Could not start {} threads: {}", threads, err))?,
+            None => {
+                let mut pool = SearchPool::default();
+                pool.parallel_depth = self.parallel_depth;
+                pool
+            }
+        };
+
+        if let Some(path) = &self.book {
+            pool.book = Some(Arc::new(Book::load(Path::new(path))?));
+        }
+
+        Ok(pool)
+    }
+
+    /// Entries the table holds, from `--hash-mb` or the default
+    pub fn table_entries(&self) -> usize {
+        self.hash_mb.map(entries_for_mb).unwrap_or(DEFAULT_TABLE_ENTRIES)
+    }
+
+    /// A new table sized by `--hash-mb`, only for commands which search as
+    /// the default one takes over three gigabytes
+    pub fn hash_table(&self) -> HashTable {
+        new_hash_table(self.table_entries())
+    }
+
+    /// A table sized by `--hash-mb`, or starting at `entries` when it wasn't
+    /// given, for commands which only search a handful of positions
+    pub fn hash_table_or(&self, entries: usize) -> HashTable {
+        new_hash_table(self.hash_mb.map(entries_for_mb).unwrap_or(entries))
+    }
+}
+
+pub fn validate_board(board: &str) -> Result<(), String> {
+    if board.len() != BOARD_SIZE {
+        return Err(format!(
+            "We need a string of {} length, this was {}",
+            BOARD_SIZE,
+            board.len()
+        ));
+    }
+
+    if let Some(bad) = board.chars().find(|c| !c.is_ascii_digit()) {
+        return Err(format!("Boards are made of digits, found '{}'", bad));
+    }
+
+    Ok(())
+}
+
+fn parse_depths(value: Option<String>) -> Result<Vec<u8>, String> {
+    let value = value.ok_or("--depths needs a value")?;
+
+    value
+        .split(',')
+        .map(|depth| match depth.trim().parse() {
+            Ok(0) => Err("--depths must all be at least 1".to_string()),
+            Ok(depth) => Ok(depth),
+            Err(_) => Err(format!("--depths expects numbers, got '{}'", depth)),
+        })
+        .collect()
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a value", flag))?;
+
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got '{}'", flag, value))
+}
+
+/// Parses the arguments after the program name
+pub fn parse_args(args: &[String]) -> Result<(Command, Options), String> {
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut highlight = None;
+    let mut turns = 20;
+    let mut port = None;
+    let mut bench = BenchOptions::default();
+    let mut animate = false;
+    let mut frame_time = DEFAULT_FRAME_TIME;
+    let mut out = None;
+    let mut pv = 0;
+    let mut calibration = None;
+    let mut sprites = "sprites".to_string();
+    let mut record = None;
+
+    let mut iter = args.iter().cloned();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--depth" => options.depth = parse_number(&arg, iter.next())?,
+            "--water" => options.water = parse_number(&arg, iter.next())?,
+            "--rise" => options.tide.rise = parse_number(&arg, iter.next())?,
+            "--pump" => options.tide.pump_score = parse_number(&arg, iter.next())?,
+            "--threads" => options.threads = Some(parse_number(&arg, iter.next())?),
+            "--parallel-depth" => options.parallel_depth = parse_number(&arg, iter.next())?,
+            "--hash-mb" => options.hash_mb = Some(parse_number(&arg, iter.next())?),
+            "--time" => {
+                options.time = Some(Duration::from_millis(parse_number(&arg, iter.next())?))
+            }
+            "--table-file" => {
+                options.table_file = Some(iter.next().ok_or("--table-file needs a file")?)
+            }
+            "--book" => options.book = Some(iter.next().ok_or("--book needs a file")?),
+            "--move" => highlight = Some(parse_number(&arg, iter.next())?),
+            "--animate" => animate = true,
+            "--out" => out = Some(iter.next().ok_or("--out needs a file")?),
+            "--pv" => pv = parse_number(&arg, iter.next())?,
+            "--calibration" => {
+                calibration = Some(iter.next().ok_or("--calibration needs x,y,cell")?)
+            }
+            "--sprites" => sprites = iter.next().ok_or("--sprites needs a directory")?,
+            "--frame-ms" => frame_time = Duration::from_millis(parse_number(&arg, iter.next())?),
+            "--turns" => turns = parse_number(&arg, iter.next())?,
+            "--record" => record = Some(iter.next().ok_or("--record needs a file")?),
+            "--port" => port = Some(parse_number(&arg, iter.next())?),
+            "--depths" => bench.depths = parse_depths(iter.next())?,
+            "--runs" => bench.runs = parse_number(&arg, iter.next())?,
+            "--baseline" => bench.baseline = Some(iter.next().ok_or("--baseline needs a file")?),
+            "--save-baseline" => {
+                bench.save_baseline = Some(iter.next().ok_or("--save-baseline needs a file")?)
+            }
+            "--threshold" => bench.threshold = parse_number(&arg, iter.next())?,
+            "--format" => {
+                let value = iter.next().ok_or("--format needs a value, text or json")?;
+                options.format = OutputFormat::parse(&value)
+                    .ok_or(format!("Unknown format {}, use text or json", value))?;
+            }
+            "-h" | "--help" => return Ok((Command::Help, options)),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next() {
+        None => {
+            check_options(&options)?;
+            return Ok((Command::Bench(bench), options));
+        }
+        Some(command) => command,
+    };
+
+    let board_arg = |board: Option<String>| -> Result<String, String> {
+        let board = board.ok_or(format!("{} needs a board", command))?;
+        validate_board(&board)?;
+        Ok(board)
+    };
+
+    let parsed = match command.as_str() {
+        "solve" => Command::Solve {
+            board: board_arg(positional.next())?,
+        },
+        "serve" | "stdin" => Command::Serve { port },
+        "bench" => Command::Bench(BenchOptions {
+            corpus: positional.next(),
+            ..bench
+        }),
+        "draw" => {
+            if animate && highlight.is_none() {
+                return Err("--animate needs a --move to play".to_string());
+            }
+
+            Command::Draw {
+                board: board_arg(positional.next())?,
+                highlight,
+                animate: if animate { Some(frame_time) } else { None },
+            }
+        }
+        "render" => Command::Render {
+            board: board_arg(positional.next())?,
+            out: out.ok_or("render needs an --out file")?,
+            highlight,
+            pv,
+        },
+        "read" => Command::Read {
+            screenshot: positional.next().ok_or("read needs a screenshot")?,
+            calibration: calibration.ok_or("read needs --calibration x,y,cell")?,
+            sprites,
+        },
+        "simulate" => Command::Simulate {
+            board: match positional.next() {
+                Some(board) => Some(board_arg(Some(board))?),
+                None => None,
+            },
+            turns,
+            record,
+        },
+        "replay" => Command::Replay {
+            record: positional.next().ok_or("replay needs a record file")?,
+        },
+        "book" => match positional.next().as_deref() {
+            Some("build") => Command::BuildBook {
+                corpus: positional.next(),
+                out: out.ok_or("book build needs an --out file")?,
+            },
+            _ => return Err("book needs a subcommand, build".to_string()),
+        },
+        "analyze" => match positional.next() {
+            // Anything but digits is taken for a record file
+            Some(record) if !record.chars().all(|c| c.is_ascii_digit()) => {
+                Command::AnalyzeRecord { record }
+            }
+            board => Command::Analyze {
+                board: board_arg(board)?,
+            },
+        },
+        "help" => Command::Help,
+        _ => {
+            // The old `<board> <depth> <water>` form
+            let depth = positional.next();
+            let water = positional.next();
+
+            if depth.is_none() || water.is_none() {
+                return Err(format!("Unknown command {}", command));
+            }
+
+            validate_board(&command)?;
+            options.depth = parse_number("depth", depth)?;
+            options.water = parse_number("water", water)?;
+
+            Command::Solve { board: command }
+        }
+    };
+
+    if let Some(extra) = positional.next() {
+        return Err(format!("Unexpected argument {}", extra));
+    }
+
+    check_options(&options)?;
+    Ok((parsed, options))
+}
+
+/// Run once every way of setting the options has been parsed, the old
+/// `<board> <depth> <water>` form included
+fn check_options(options: &Options) -> Result<(), String> {
+    if options.depth == 0 {
+        return Err("--depth must be at least 1".to_string());
+    }
+
+    if options.water > MAX_WATER_LEVEL {
+        return Err(format!("--water can be at most {}", MAX_WATER_LEVEL));
+    }
+
+    if options.threads == Some(0) {
+        return Err("--threads must be at least 1".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TEST_BOARD;
+
+    fn args(input: &str) -> Vec<String> {
+        input.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_flags() {
+        let (command, options) = parse_args(&args(&format!(
+            "solve {} --depth 7 --water 2 --threads 4 --parallel-depth 5 --hash-mb 256 --time 500 --format json --rise 1 --pump 50",
+            TEST_BOARD
+        )))
+        .unwrap();
+
+        assert_eq!(
+            command,
+            Command::Solve {
+                board: TEST_BOARD.to_string()
+            }
+        );
+        assert_eq!(options.depth, 7);
+        assert_eq!(options.water, 2);
+        assert_eq!(options.threads, Some(4));
+        assert_eq!(options.parallel_depth, 5);
+        assert_eq!(options.search_pool().unwrap().threads(), 4);
+        assert_eq!(options.hash_mb, Some(256));
+        assert_eq!(options.time, Some(Duration::from_millis(500)));
+        assert_eq!(options.format, OutputFormat::Json);
+        assert_eq!(options.tide, Tide { rise: 1, pump_score: 50 });
+        assert_eq!(options.game(TEST_BOARD).tide, options.tide);
+    }
+
+    #[test]
+    fn test_legacy_form() {
+        let (command, options) = parse_args(&args(&format!("{} 5 1", TEST_BOARD))).unwrap();
+
+        assert_eq!(
+            command,
+            Command::Solve {
+                board: TEST_BOARD.to_string()
+            }
+        );
+        assert_eq!(options.depth, 5);
+        assert_eq!(options.water, 1);
+
+        // Checked the same as --depth and --water
+        let zero_depth = parse_args(&args(&format!("{} 0 3", TEST_BOARD)));
+        assert_eq!(zero_depth, Err("--depth must be at least 1".to_string()));
+        let drowned = parse_args(&args(&format!("{} 3 12", TEST_BOARD)));
+        assert_eq!(drowned, Err("--water can be at most 11".to_string()));
+
+        assert_eq!(parse_args(&[]).unwrap().0, Command::Bench(BenchOptions::default()));
+    }
+
+    #[test]
+    fn test_bench_flags() {
+        let (command, _) = parse_args(&args(
+            "bench boards.txt --depths 5,6 --runs 1 --baseline old.json --save-baseline new.json --threshold 25",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            command,
+            Command::Bench(BenchOptions {
+                corpus: Some("boards.txt".to_string()),
+                depths: vec![5, 6],
+                runs: 1,
+                baseline: Some("old.json".to_string()),
+                save_baseline: Some("new.json".to_string()),
+                threshold: 25.0,
+            })
+        );
+
+        assert!(parse_args(&args("bench --depths 5,0")).is_err());
+        assert!(parse_args(&args("bench --depths five")).is_err());
+        assert!(parse_args(&args("bench a.txt b.txt")).is_err());
+    }
+
+    #[test]
+    fn test_animate() {
+        let (command, _) = parse_args(&args(&format!(
+            "draw {} --move 39 --animate --frame-ms 250",
+            TEST_BOARD
+        )))
+        .unwrap();
+
+        assert_eq!(
+            command,
+            Command::Draw {
+                board: TEST_BOARD.to_string(),
+                highlight: Some(39),
+                animate: Some(Duration::from_millis(250)),
+            }
+        );
+
+        assert!(parse_args(&args(&format!("draw {} --animate", TEST_BOARD))).is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let (command, _) = parse_args(&args(&format!(
+            "render {} --out board.svg --move 39 --pv 3",
+            TEST_BOARD
+        )))
+        .unwrap();
+
+        assert_eq!(
+            command,
+            Command::Render {
+                board: TEST_BOARD.to_string(),
+                out: "board.svg".to_string(),
+                highlight: Some(39),
+                pv: 3,
+            }
+        );
+
+        assert!(parse_args(&args(&format!("render {}", TEST_BOARD))).is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let (command, _) =
+            parse_args(&args("read shot.png --calibration 37,52,40 --sprites client")).unwrap();
+
+        assert_eq!(
+            command,
+            Command::Read {
+                screenshot: "shot.png".to_string(),
+                calibration: "37,52,40".to_string(),
+                sprites: "client".to_string(),
+            }
+        );
+
+        assert!(parse_args(&args("read shot.png")).is_err());
+        assert!(parse_args(&args("read --calibration 37,52,40")).is_err());
+    }
+
+    #[test]
+    fn test_records() {
+        let (command, _) = parse_args(&args("simulate --turns 5 --record game.jsonl")).unwrap();
+
+        assert_eq!(
+            command,
+            Command::Simulate {
+                board: None,
+                turns: 5,
+                record: Some("game.jsonl".to_string()),
+            }
+        );
+
+        let (command, options) = parse_args(&args("replay game.jsonl --depth 4")).unwrap();
+
+        assert_eq!(
+            command,
+            Command::Replay {
+                record: "game.jsonl".to_string()
+            }
+        );
+        assert_eq!(options.depth, 4);
+
+        assert!(parse_args(&args("replay")).is_err());
+
+        let (command, _) = parse_args(&args("analyze game.jsonl")).unwrap();
+        assert_eq!(
+            command,
+            Command::AnalyzeRecord {
+                record: "game.jsonl".to_string()
+            }
+        );
+
+        let (command, _) = parse_args(&args(&format!("analyze {}", TEST_BOARD))).unwrap();
+        assert_eq!(
+            command,
+            Command::Analyze {
+                board: TEST_BOARD.to_string()
+            }
+        );
+
+        assert!(parse_args(&args("analyze 1234")).is_err());
+    }
+
+    #[test]
+    fn test_book() {
+        let (command, _) =
+            parse_args(&args("book build boards.txt --out book.bin --depth 7")).unwrap();
+
+        assert_eq!(
+            command,
+            Command::BuildBook {
+                corpus: Some("boards.txt".to_string()),
+                out: "book.bin".to_string(),
+            }
+        );
+
+        let (_, options) =
+            parse_args(&args(&format!("solve {} --book missing/book.bin", TEST_BOARD))).unwrap();
+        assert_eq!(options.book, Some("missing/book.bin".to_string()));
+        assert!(options.search_pool().is_err());
+
+        assert!(parse_args(&args("book build")).is_err());
+        assert!(parse_args(&args("book --out book.bin")).is_err());
+    }
+
+    #[test]
+    fn test_table_file() {
+        let (command, options) = parse_args(&args("serve --table-file table.bin")).unwrap();
+
+        assert_eq!(command, Command::Serve { port: None });
+        assert_eq!(options.table_file, Some("table.bin".to_string()));
+        assert!(parse_args(&args("serve --table-file")).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_args(&args("solve 1234")).is_err());
+        assert!(parse_args(&args("solve")).is_err());
+        assert!(parse_args(&args("bench --depth")).is_err());
+        assert!(parse_args(&args("bench --depth seven")).is_err());
+        assert!(parse_args(&args("bench --frobnicate 1")).is_err());
+        assert!(parse_args(&args("fly")).is_err());
+        assert!(parse_args(&args("bench --water 12")).is_err());
+    }
+}
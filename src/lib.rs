@@ -1,9 +1,16 @@
 #![feature(thread_local)]
 #![feature(core_intrinsics)]
-#![feature(stdsimd)]
+#![allow(internal_features)]
 
 #[macro_use]
 mod macros;
 
+pub mod analysis;
+pub mod bench;
 pub mod board;
+pub mod cli;
 pub mod config;
+pub mod output;
+pub mod protocol;
+pub mod record;
+pub mod server;
@@ -1,20 +1,23 @@
-#![feature(thread_local)]
-#![feature(core_intrinsics)]
-#![feature(stdsimd)]
-
-#[macro_use]
-mod macros;
-
-pub mod board;
-
-use bilge::config::TEST_BOARD;
+use bilge::analysis;
+use bilge::bench::{self, Baseline, BenchResult};
+use bilge::board;
+use bilge::cli::{parse_args, BenchOptions, Command, Options, USAGE};
+use bilge::output::{print_search, OutputFormat, SearchReport};
+use bilge::protocol;
+use bilge::record::{self, GameRecord, RecordWriter, RecordedTurn};
+use bilge::server::Server;
 use board::helpers::move_to_dani_move;
+use board::searcher::{SearchPool, TurnList};
+use board::render::RenderOptions;
+use board::book::Book;
+use board::persist;
+use board::GameState;
 
 use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
 
-use ahash::RandomState;
-
 use board::searcher::HashTable;
 
 #[global_allocator]
@@ -22,114 +25,768 @@ static ALLOC: snmalloc_rs::SnMalloc = snmalloc_rs::SnMalloc;
 //static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let random = RandomState::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let (command, options) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match command {
+        Command::Solve { board } => {
+            let pool = search_pool(&options);
+            solve(&board, &options, &options.hash_table(), &pool)
+        }
+        Command::Serve { port } => {
+            let pool = search_pool(&options);
+            serve(port, &options, options.hash_table(), pool)
+        }
+        Command::Bench(bench_options) => {
+            let pool = search_pool(&options);
+            let result = run_bench(&bench_options, &options, &options.hash_table(), &pool);
+
+            if let Err(err) = result {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Draw {
+            board,
+            highlight: Some(pos),
+            animate: Some(frame_time),
+        } => {
+            if let Err(reason) = options.game(&board).animate_swap(pos, frame_time) {
+                eprintln!("Can't play move {}, {}", pos, reason);
+                std::process::exit(1);
+            }
+        }
+        Command::Draw { board, highlight, .. } => {
+            options.game(&board).draw_highlight(highlight.unwrap_or(99))
+        }
+        Command::Render {
+            board,
+            out,
+            highlight,
+            pv,
+        } => {
+            if let Err(err) = render(&board, &out, highlight, pv, &options) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Read {
+            screenshot,
+            calibration,
+            sprites,
+        } => match read_screenshot(&screenshot, &calibration, &sprites) {
+            Ok(game) => print_read(&game),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        Command::Simulate {
+            board,
+            turns,
+            record,
+        } => {
+            let pool = search_pool(&options);
+            let hash_table = options.hash_table();
+
+            if let Err(err) = simulate(board, turns, record, &options, &hash_table, &pool) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::BuildBook { corpus, out } => {
+            let pool = search_pool(&options);
+
+            if let Err(err) = build_book(corpus, &out, &options, &options.hash_table(), &pool) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Replay { record } => {
+            let pool = search_pool(&options);
+
+            if let Err(err) = replay(&record, &options, &options.hash_table(), &pool) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Analyze { board } => {
+            let pool = search_pool(&options);
+            analyze(&board, &options, &options.hash_table(), &pool)
+        }
+        Command::AnalyzeRecord { record } => {
+            let pool = search_pool(&options);
+
+            if let Err(err) = analyze_record(&record, &options, &options.hash_table(), &pool) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Help => println!("{}", USAGE),
+    }
+}
+
+/// The pool and book for commands which search, the others never load the
+/// book or start threads
+fn search_pool(options: &Options) -> SearchPool {
+    match options.search_pool() {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the protocol over stdin, or the HTTP server with a port. With
+/// `--table-file` the table is loaded before and saved after, or on Ctrl-C
+/// or SIGTERM for the server as it never stops by itself.
+fn serve(port: Option<u16>, options: &Options, hash_table: HashTable, pool: SearchPool) {
+    let hash_table = Arc::new(hash_table);
+    let table_file = options.table_file.as_ref().map(PathBuf::from);
+
+    if let Some(path) = &table_file {
+        // A missing file is the first run, a bad one costs a cold start
+        if path.exists() {
+            match persist::load_table(&hash_table, path, options.table_entries()) {
+                Ok(loaded) => eprintln!("Loaded {} table entries from {}", loaded, path.display()),
+                Err(err) => eprintln!("{}, starting with an empty table", err),
+            }
+        }
+
+        // Over stdin `setoption name Hash` can swap the table out from
+        // under a handler, so only the server saves this way
+        if port.is_some() {
+            let table = hash_table.clone();
+            let path = path.clone();
+            let max_entries = options.table_entries();
 
-    let mut hash_table: HashTable = dashmap::DashMap::with_capacity_and_hasher(40_000_000, random);
+            let handler = ctrlc::set_handler(move || {
+                save_table(&table, &path, max_entries);
+                std::process::exit(0);
+            });
 
-    let arg_count = args.len();
+            if let Err(err) = handler {
+                eprintln!("Could not catch Ctrl-C, the table won't be saved: {}", err);
+            }
+        }
+    }
+
+    let (hash_table, max_entries) = match port {
+        None => {
+            let stdin = std::io::stdin();
+            protocol::run(stdin.lock(), std::io::stdout(), options, hash_table, pool)
+        }
+        Some(port) => match Server::bind(port, options, hash_table.clone(), pool) {
+            Ok(server) => {
+                println!("Listening on http://{}", server.local_addr().unwrap());
+                server.run();
+                (hash_table, options.table_entries())
+            }
+            Err(err) => {
+                eprintln!("Could not listen on port {}: {}", port, err);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    if let Some(path) = &table_file {
+        save_table(&hash_table, path, max_entries);
+    }
+}
+
+/// Saves no more entries than `--hash-mb` holds, the table can be past
+/// that by what the last search added before it was trimmed again
+fn save_table(hash_table: &HashTable, path: &Path, max_entries: usize) {
+    match persist::save_table(hash_table, path, max_entries) {
+        Ok(saved) => eprintln!("Saved {} table entries to {}", saved, path.display()),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+/// Searches to the requested depth, or deepens until the time runs out
+fn run_search(
+    game: &GameState,
+    options: &Options,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> (TurnList, u8) {
+    match options.time {
+        Some(time) => {
+            board::searcher::find_best_move_timed(game, options.depth, time, hash_table, pool)
+        }
+        None => (
+            board::searcher::find_best_move_list_in(game, options.depth, false, hash_table, pool),
+            options.depth,
+        ),
+    }
+}
+
+fn solve(board: &str, options: &Options, hash_table: &HashTable, pool: &SearchPool) {
+    let now = Instant::now();
+    let game = options.game(board);
+    let (best_moves, depth) = run_search(&game, options, hash_table, pool);
+
+    if options.format == OutputFormat::Json {
+        print_search(options.format, &best_moves, depth, options.water, now.elapsed());
+        return;
+    }
 
-    if arg_count == 4 {
-        if args[1].len() != 72 {
-            println!("We need a string of 72 length, this was {}", args[1].len());
+    let best_move = match best_moves.turns.first() {
+        Some(best_move) => best_move,
+        None => {
+            println!("Couldnt find any moves");
             return;
         }
+    };
+
+    println!(
+        "{} {} ran at depth {}, {}, best move {} with score {}, it took {:?}",
+        move_to_dani_move(best_move.turn),
+        best_move.score,
+        depth,
+        best_moves.info_str,
+        best_move.turn,
+        best_move.score,
+        now.elapsed()
+    )
+}
 
-        let water_level = u8::from_str_radix(&args[3], 10).unwrap();
-        let depth = u8::from_str_radix(&args[2], 10).unwrap();
+fn analyze(board: &str, options: &Options, hash_table: &HashTable, pool: &SearchPool) {
+    let now = Instant::now();
+    let game = options.game(board);
+    let (best_moves, depth) = run_search(&game, options, hash_table, pool);
 
-        let now = Instant::now();
-        let game = board::board_from_str(&args[1], water_level);
-        let best_move = board::searcher::find_best_move(&game, depth, false, &hash_table);
-        let dani_move = move_to_dani_move(best_move.turn);
+    if options.format == OutputFormat::Json {
+        print_search(options.format, &best_moves, depth, options.water, now.elapsed());
+        return;
+    }
 
+    game.draw();
+
+    for (rank, info) in best_moves.turns.iter().enumerate() {
         println!(
-            "{} {} ran at depth {}, {}, it took {:?}",
-            dani_move,
-            best_move.score,
-            depth,
-            best_move.info_str,
-            now.elapsed()
-        )
-    } else if arg_count == 2 || arg_count == 1 {
-        // Benchmarking mode
-        if arg_count == 1 || args[1] == "bench" {
-            bench(&mut hash_table);
-            return;
+            "{:>2}. move {:>2} (index {:>2}) score {} {}",
+            rank + 1,
+            move_to_dani_move(info.turn),
+            info.turn,
+            info.score,
+            info.combo.name()
+        );
+    }
+
+    println!(
+        "Depth {}, {} took {:?}",
+        depth,
+        best_moves.info_str,
+        now.elapsed()
+    );
+}
+
+/// Ranks every move of a recorded game, then sums up where the points went
+fn analyze_record(
+    path: &str,
+    options: &Options,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> Result<(), String> {
+    let input = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read record {}: {}", path, err))?;
+    let game_record = GameRecord::from_jsonl(&input)?;
+
+    let reviews = analysis::analyze_record(&game_record, options.depth, hash_table, pool)?;
+    let summary = analysis::summarize(&reviews);
+
+    if options.format == OutputFormat::Json {
+        for review in reviews.iter() {
+            println!("{}", serde_json::to_string(review).unwrap());
         }
 
-        let mut input = String::with_capacity(200);
+        println!("{}", serde_json::to_string(&summary).unwrap());
+        return Ok(());
+    }
 
-        while let Ok(_read) = std::io::stdin().read_line(&mut input) {
-            let len = input.trim_end_matches(&['\r', '\n'][..]).len();
-            input.truncate(len);
+    for review in reviews.iter() {
+        let verdict = if review.rank == 1 {
+            "the best move".to_string()
+        } else {
+            format!(
+                "{} behind move {} ({})",
+                review.lost,
+                move_to_dani_move(review.best_move),
+                review.best_combo.name()
+            )
+        };
+
+        println!(
+            "Turn {:>3} move {:>2} scored {}, ranked {} of {}, {}{}",
+            review.number,
+            move_to_dani_move(review.turn),
+            review.scored,
+            review.rank,
+            review.moves,
+            verdict,
+            if review.is_mistake() { ", a mistake" } else { "" }
+        );
+    }
 
-            let commands: Vec<&str> = input.split(' ').collect();
+    println!(
+        "Depth {}, {} mistakes over {} turns losing {}, the move played ranked {:.1} on average",
+        options.depth, summary.mistakes, summary.turns, summary.lost, summary.average_rank
+    );
 
-            if commands.len() < 3 {
-                println!("Not enough parameters");
-                continue;
-            }
+    if !summary.worst.is_empty() {
+        let worst: Vec<String> = summary.worst.iter().map(|number| number.to_string()).collect();
+        println!("Worst turns: {}", worst.join(", "));
+    }
 
-            let water_level = u8::from_str_radix(commands[2], 10).unwrap();
-            let depth = u8::from_str_radix(commands[1], 10).unwrap();
+    if summary.missed.is_empty() {
+        println!("No combos missed");
+    }
 
-            let now = Instant::now();
-            let game = board::board_from_str(&commands[0], water_level);
-            let best_moves = board::searcher::find_best_move_list(&game, depth, false, &hash_table);
-            let best_move = best_moves.turns.get(0);
+    for missed in summary.missed.iter() {
+        let turns: Vec<String> = missed.turns.iter().map(|number| number.to_string()).collect();
+        println!(
+            "Missed {} {} losing {}, turns {}",
+            missed.count,
+            missed.combo.name(),
+            missed.lost,
+            turns.join(", ")
+        );
+    }
 
-            if best_move.is_none() {
-                println!("Couldnt find any moves");
-                continue;
+    Ok(())
+}
+
+/// The engine's next `length` moves, played on the board as it is with
+/// nothing filling the gaps
+fn principal_variation(
+    game: &GameState,
+    length: usize,
+    options: &Options,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> Vec<usize> {
+    let mut game = *game;
+    let mut line = Vec::new();
+
+    for _ in 0..length {
+        let (best_moves, _) = run_search(&game, options, hash_table, pool);
+
+        let best_move = match best_moves.turns.first() {
+            Some(best_move) => best_move.turn,
+            None => break,
+        };
+
+        match game.swap(best_move) {
+            Ok(swapped) => game.apply_tide(swapped.score),
+            Err(_) => break,
+        }
+
+        line.push(best_move);
+    }
+
+    line
+}
+
+/// The table `render --pv` starts with, a few moves don't need the
+/// gigabytes a long session does
+const PV_TABLE_ENTRIES: usize = 1 << 18;
+
+fn render(
+    board: &str,
+    out: &str,
+    highlight: Option<usize>,
+    pv: usize,
+    options: &Options,
+) -> Result<(), String> {
+    let game = options.game(board);
+
+    if let Some(pos) = highlight {
+        game.validate_swap(pos)
+            .map_err(|reason| format!("Can't box move {}, {}", pos, reason))?;
+    }
+
+    let arrows = match pv {
+        0 => Vec::new(),
+        _ => {
+            let pool = options.search_pool()?;
+            principal_variation(&game, pv, options, &options.hash_table_or(PV_TABLE_ENTRIES), &pool)
+        }
+    };
+
+    let render_options = RenderOptions {
+        highlight: highlight.or_else(|| arrows.first().copied()),
+        arrows,
+        ..RenderOptions::default()
+    };
+
+    let contents = if out.ends_with(".png") {
+        render_png(&game, &render_options)?
+    } else {
+        board::render::render_svg(&game, &render_options).into_bytes()
+    };
+
+    std::fs::write(out, contents).map_err(|err| format!("Could not write {}: {}", out, err))
+}
+
+#[cfg(feature = "png")]
+fn render_png(game: &GameState, render_options: &RenderOptions) -> Result<Vec<u8>, String> {
+    board::render::render_png(game, render_options)
+}
+
+#[cfg(not(feature = "png"))]
+fn render_png(_: &GameState, _: &RenderOptions) -> Result<Vec<u8>, String> {
+    Err("This build can't write PNGs, build with --features png or write an .svg".to_string())
+}
+
+#[cfg(feature = "png")]
+fn read_screenshot(screenshot: &str, calibration: &str, sprites: &str) -> Result<GameState, String> {
+    use board::vision::{Calibration, Image, Sprites};
+    use std::path::Path;
+
+    let calibration = Calibration::parse(calibration)?;
+    let sprites = Sprites::load(Path::new(sprites))?;
+    let shot = Image::load(Path::new(screenshot))?;
+
+    sprites.read_board(&shot, &calibration)
+}
+
+#[cfg(not(feature = "png"))]
+fn read_screenshot(_: &str, _: &str, _: &str) -> Result<GameState, String> {
+    Err("This build can't read screenshots, build with --features png".to_string())
+}
+
+/// Draws what was read, then the board and water the way `solve` and the
+/// bench corpus take them. Boards with gaps have no digit form.
+fn print_read(game: &GameState) {
+    game.draw();
+
+    match record::board_digits(game) {
+        Some(digits) => println!("{} {}", digits, game.water_level),
+        None => println!("The board has empty cells, wait for it to settle"),
+    }
+}
+
+fn simulate(
+    board: Option<String>,
+    turns: usize,
+    record_path: Option<String>,
+    options: &Options,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> Result<(), String> {
+    let mut game = match board {
+        Some(board) => options.game(&board),
+        None => {
+            let mut game = board::generate_rand_board();
+            game.water_level = options.water;
+            game.tide = options.tide;
+            game
+        }
+    };
+
+    let mut recording = match &record_path {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .map_err(|err| format!("Could not save the record {}: {}", path, err))?;
+            Some(RecordWriter::new(file, &game)?)
+        }
+        None => None,
+    };
+    let mut total = 0.0;
+
+    for turn in 0..turns {
+        let now = Instant::now();
+        let (best_moves, depth) = run_search(&game, options, hash_table, pool);
+
+        let best_move = match best_moves.turns.first() {
+            Some(best_move) => *best_move,
+            None => {
+                println!("No moves left after {} turns", turn);
+                break;
             }
+        };
+
+        if options.format == OutputFormat::Json {
+            let report = SearchReport::new(&best_moves, depth, game.water_level, now.elapsed());
+            println!("{}", report.to_json());
+        } else {
+            game.draw_highlight(best_move.turn);
+        }
 
-            let best_move = best_move.unwrap();
+        let swapped = match game.swap(best_move.turn) {
+            Ok(swapped) => swapped,
+            Err(reason) => {
+                println!("Move {} can't be made, {}", best_move.turn, reason);
+                break;
+            }
+        };
+        let (score, combo) = (swapped.score, swapped.combo);
+
+        let gaps = record::gaps(&game);
+        board::fill_cleared(&mut game);
+        game.apply_tide(score);
+        total += score;
+
+        if let Some(recording) = recording.as_mut() {
+            recording.push(&RecordedTurn {
+                turn: best_move.turn,
+                score,
+                refill: gaps.iter().map(|pos| game.board[*pos]).collect(),
+                suggested: Some(best_move.turn),
+                suggested_score: Some(best_move.score),
+            })?;
+        }
 
-            let dani_move = move_to_dani_move(best_move.turn);
+        if options.format == OutputFormat::Text {
             println!(
-                "{} {} ran at depth {}, {} took {:?}",
-                dani_move,
-                best_move.score,
-                depth,
-                best_moves.info_str,
-                now.elapsed()
+                "Turn {} move {} made a {} scoring {}, total {}, water at {}",
+                turn + 1,
+                move_to_dani_move(best_move.turn),
+                combo.name(),
+                score,
+                total,
+                game.water_level
             );
+        }
+    }
+
+    if options.format == OutputFormat::Text {
+        game.draw();
+        println!("Finished with a score of {}", total);
+    }
+
+    Ok(())
+}
+
+/// Steps through a recorded game, searching every position again to see
+/// how each move compares with the best one
+fn replay(
+    path: &str,
+    options: &Options,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> Result<(), String> {
+    let input = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read record {}: {}", path, err))?;
+    let game_record = GameRecord::from_jsonl(&input)?;
+
+    let mut total = 0.0;
+    let mut lost = 0.0;
+    let mut mistakes = 0;
+
+    for position in game_record.positions()?.iter() {
+        let turn = position.turn;
+        let (best_moves, depth) = run_search(&position.game, options, hash_table, pool);
+
+        position.game.draw_highlight(turn.turn);
+        total += turn.score;
+
+        let suggested = match (turn.suggested, turn.suggested_score) {
+            (Some(suggested), Some(score)) => format!(
+                ", the engine suggested move {} for {}",
+                move_to_dani_move(suggested),
+                score
+            ),
+            (Some(suggested), None) => {
+                format!(", the engine suggested move {}", move_to_dani_move(suggested))
+            }
+            _ => String::new(),
+        };
 
-            input = String::with_capacity(200);
+        println!(
+            "Turn {} move {} scored {}{}",
+            position.number,
+            move_to_dani_move(turn.turn),
+            turn.score,
+            suggested
+        );
 
-            let random = RandomState::new();
-            hash_table = dashmap::DashMap::with_capacity_and_hasher(40_000_000, random);
+        match analysis::review(position, &best_moves) {
+            Some(review) if review.is_mistake() => {
+                mistakes += 1;
+                lost += review.lost;
+                println!(
+                    "Mistake: at depth {} move {} scores {}, {} more than this move",
+                    depth,
+                    move_to_dani_move(review.best_move),
+                    review.best_score,
+                    review.lost
+                );
+            }
+            Some(_) => println!(
+                "Fine: within {} of the best at depth {}",
+                record::MISTAKE_MARGIN,
+                depth
+            ),
+            None => println!("The search at depth {} doesn't list this move", depth),
         }
     }
-}
 
-fn bench(map: &mut HashTable) {
-    let game = board::board_from_str(TEST_BOARD, 3);
+    println!(
+        "{} turns scored {}, {} mistakes losing {} by the engine's count",
+        game_record.turns.len(),
+        total,
+        mistakes,
+        lost
+    );
 
-    game.draw_highlight(39);
+    Ok(())
+}
+
+/// Searches every board of the corpus and adds the best moves to the book
+/// at `out`, starting a new one when there isn't one yet
+fn build_book(
+    corpus: Option<String>,
+    out: &str,
+    options: &Options,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+) -> Result<(), String> {
+    let corpus = match &corpus {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read corpus {}: {}", path, err))?,
+        None => bench::DEFAULT_CORPUS.to_string(),
+    };
+
+    let boards = bench::parse_corpus(&corpus)?;
+    let path = Path::new(out);
+
+    let mut book = if path.exists() {
+        Book::load(path)?
+    } else {
+        Book::new()
+    };
+
+    for (number, entry) in boards.iter().enumerate() {
+        let mut game = entry.game();
+        game.tide = options.tide;
 
-    let run_count = 10;
-    let mut average = 0;
-    
-    for i in 0..run_count {
         let now = Instant::now();
-        let _best_moves = board::searcher::find_best_move_list(&game, 7, false, map);
-        let time_taken = now.elapsed();
+        let best_moves =
+            board::searcher::find_best_move_list_in(&game, options.depth, false, hash_table, pool);
+
+        let best_move = match best_moves.turns.first() {
+            Some(best_move) => best_move,
+            None => {
+                println!("Board {:>2} has no moves, skipped", number + 1);
+                continue;
+            }
+        };
 
+        book.insert(&game, &best_moves, options.depth);
         println!(
-            "Run {} took {:?} best move {:?}, hashtable size {}",
-            i + 1,
-            time_taken,
-            _best_moves.turns.get(0).unwrap(),
-            map.len()
+            "Board {:>2} move {} score {} at depth {}, took {:?}",
+            number + 1,
+            move_to_dani_move(best_move.turn),
+            best_move.score,
+            options.depth,
+            now.elapsed()
         );
+    }
+
+    book.save(path)?;
+    println!("Saved {} positions to {}", book.len(), out);
+
+    Ok(())
+}
+
+/// Searches every board of the corpus at every depth, then checks the
+/// results against a baseline when given one
+fn run_bench(
+    bench_options: &BenchOptions,
+    options: &Options,
+    map: &HashTable,
+    pool: &SearchPool,
+) -> Result<(), String> {
+    let corpus = match &bench_options.corpus {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read corpus {}: {}", path, err))?,
+        None => bench::DEFAULT_CORPUS.to_string(),
+    };
+
+    let boards = bench::parse_corpus(&corpus)?;
+
+    // Read before searching so a bad path doesn't waste the whole run
+    let baseline = match &bench_options.baseline {
+        Some(path) => Some(Baseline::from_json(
+            &std::fs::read_to_string(path)
+                .map_err(|err| format!("Could not read baseline {}: {}", path, err))?,
+        )?),
+        None => None,
+    };
+
+    let depths = if bench_options.depths.is_empty() {
+        vec![options.depth]
+    } else {
+        bench_options.depths.clone()
+    };
+
+    let mut results: Vec<BenchResult> = Vec::new();
+
+    for &depth in depths.iter() {
+        for (number, entry) in boards.iter().enumerate() {
+            let result =
+                bench::run_board(entry, depth, bench_options.runs, map, pool);
+            let best_move = result
+                .turn
+                .map_or("none".to_string(), |turn| move_to_dani_move(turn).to_string());
+
+            match options.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&result).unwrap()),
+                OutputFormat::Text => println!(
+                    "Board {:>2} depth {} took {:>9.2}ms, {:>6.2}M nodes/s, move {} score {}",
+                    number + 1,
+                    depth,
+                    result.elapsed_ms,
+                    result.nodes_per_sec() / 1_000_000.0,
+                    best_move,
+                    result.score
+                ),
+            }
 
-        let random = RandomState::new();
-        *map = dashmap::DashMap::with_capacity_and_hasher(40_000_000, random);
+            results.push(result);
+        }
+
+        if options.format == OutputFormat::Text {
+            let total: f64 = results
+                .iter()
+                .filter(|result| result.depth == depth)
+                .map(|result| result.elapsed_ms)
+                .sum();
+            println!("Depth {} took {:.2}ms over {} boards", depth, total, boards.len());
+        }
+    }
+
+    if let Some(path) = &bench_options.save_baseline {
+        std::fs::write(path, Baseline::new(results.clone()).to_json())
+            .map_err(|err| format!("Could not save baseline {}: {}", path, err))?;
+        eprintln!("Saved the baseline to {}", path);
+    }
+
+    let baseline = match baseline {
+        Some(baseline) => baseline,
+        None => return Ok(()),
+    };
+
+    let regressions = bench::compare(&baseline, &results, bench_options.threshold);
+
+    if regressions.is_empty() {
+        eprintln!("No regressions against the baseline");
+        return Ok(());
+    }
 
-        average += time_taken.as_millis();
+    for regression in regressions.iter() {
+        eprintln!("Regressed: {}", regression);
     }
 
-    println!("Took an average of {}ms", average / 10);
+    Err(format!("{} regressions against the baseline", regressions.len()))
 }
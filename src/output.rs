@@ -0,0 +1,210 @@
+use crate::board::helpers::move_to_dani_move;
+use crate::board::scoring::ComboKind;
+use crate::board::searcher::TurnList;
+
+use serde::Serialize;
+use std::io::Write;
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(input: &str) -> Option<OutputFormat> {
+        match input {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct MoveReport {
+    #[serde(rename = "move")]
+    pub turn: usize,
+    pub dani_move: usize,
+    pub score: f32,
+    pub combo: ComboKind,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchStats {
+    pub positions: usize,
+    pub hash_hits: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchReport {
+    pub best: Option<MoveReport>,
+    pub alternatives: Vec<MoveReport>,
+    pub depth: u8,
+    pub water_level: u8,
+    pub stats: SearchStats,
+    pub elapsed_ms: f64,
+}
+
+impl SearchReport {
+    pub fn new(list: &TurnList, depth: u8, water_level: u8, elapsed: Duration) -> SearchReport {
+        let alternatives: Vec<MoveReport> = list
+            .turns
+            .iter()
+            .map(|info| MoveReport {
+                turn: info.turn,
+                dani_move: move_to_dani_move(info.turn),
+                score: info.score,
+                combo: info.combo,
+            })
+            .collect();
+
+        let best = alternatives.first().copied();
+
+        SearchReport {
+            best,
+            alternatives,
+            depth,
+            water_level,
+            stats: SearchStats {
+                positions: list.positions,
+                hash_hits: list.hash_hits,
+            },
+            elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Prints the result of a search as one line in the chosen format
+pub fn print_search(
+    format: OutputFormat,
+    list: &TurnList,
+    depth: u8,
+    water_level: u8,
+    elapsed: Duration,
+) {
+    let stdout = std::io::stdout();
+    print_search_to(&mut stdout.lock(), format, list, depth, water_level, elapsed);
+}
+
+pub fn print_search_to<W: Write>(
+    out: &mut W,
+    format: OutputFormat,
+    list: &TurnList,
+    depth: u8,
+    water_level: u8,
+    elapsed: Duration,
+) {
+    let _ = match format {
+        OutputFormat::Json => writeln!(
+            out,
+            "{}",
+            SearchReport::new(list, depth, water_level, elapsed).to_json()
+        ),
+        OutputFormat::Text => match list.turns.first() {
+            Some(best_move) => writeln!(
+                out,
+                "{} {} ran at depth {}, {} took {:?}",
+                move_to_dani_move(best_move.turn),
+                best_move.score,
+                depth,
+                list.info_str,
+                elapsed
+            ),
+            None => writeln!(out, "Couldnt find any moves"),
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_from_str;
+    use crate::board::searcher::{find_best_move_list_in, new_hash_table, Info, SearchPool};
+    use crate::config::TEST_BOARD;
+
+    fn list(turns: Vec<Info>) -> TurnList {
+        TurnList {
+            turns,
+            positions: 1234,
+            hash_hits: 56,
+            info_str: "searched".to_string(),
+        }
+    }
+
+    fn printed(format: OutputFormat, list: &TurnList, elapsed: Duration) -> String {
+        let mut out = Vec::new();
+        print_search_to(&mut out, format, list, 6, 3, elapsed);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_json_report() {
+        let turns = vec![
+            Info {
+                turn: 39,
+                score: 12.5,
+                combo: ComboKind::Bingo,
+            },
+            Info {
+                turn: 0,
+                score: 3.0,
+                combo: ComboKind::Single,
+            },
+        ];
+
+        let elapsed = Duration::from_millis(250);
+        let json: serde_json::Value =
+            serde_json::from_str(&printed(OutputFormat::Json, &list(turns), elapsed)).unwrap();
+        assert_eq!(json["best"]["move"], 39);
+        assert_eq!(json["best"]["dani_move"], move_to_dani_move(39));
+        assert_eq!(json["best"]["combo"], "bingo");
+        assert_eq!(json["alternatives"].as_array().unwrap().len(), 2);
+        assert_eq!((json["depth"].as_u64(), json["water_level"].as_u64()), (Some(6), Some(3)));
+        assert_eq!(json["stats"]["positions"], 1234);
+        assert_eq!(json["stats"]["hash_hits"], 56);
+        assert_eq!(json["elapsed_ms"], 250.0);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&printed(OutputFormat::Json, &list(Vec::new()), elapsed)).unwrap();
+        assert!(json["best"].is_null());
+    }
+
+    #[test]
+    fn test_text_report() {
+        let turns = vec![Info {
+            turn: 39,
+            score: 12.5,
+            combo: ComboKind::Bingo,
+        }];
+
+        assert_eq!(
+            printed(OutputFormat::Text, &list(turns), Duration::from_millis(5)),
+            format!("{} 12.5 ran at depth 6, searched took 5ms\n", move_to_dani_move(39))
+        );
+        assert_eq!(
+            printed(OutputFormat::Text, &list(Vec::new()), Duration::ZERO),
+            "Couldnt find any moves\n"
+        );
+
+        assert_eq!(OutputFormat::parse("json"), Some(OutputFormat::Json));
+        assert_eq!(OutputFormat::parse("xml"), None);
+    }
+
+    #[test]
+    fn test_stats_are_counted() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let hash_table = new_hash_table(1 << 16);
+        let list = find_best_move_list_in(&game, 2, false, &hash_table, &SearchPool::default());
+        let report = SearchReport::new(&list, 2, 3, Duration::ZERO);
+
+        // Every move is searched, and below each of them every reply
+        assert!(report.stats.positions > game.get_moves().len());
+        assert!(report.stats.hash_hits < report.stats.positions);
+    }
+}
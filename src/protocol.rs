@@ -0,0 +1,544 @@
+//! Line based protocol for driving the engine over pipes, modelled on UCI
+//!
+//! ```text
+//! > bilge
+//! < id name bilgebot
+//! < option name Depth type spin default 6 min 1 max 12
+//! < bilgeok
+//! > position 3424...3456 water 3
+//! > go depth 7
+//! < info depth 1 score 12 move 33 index 39 hashfull 0 time 0
+//! < bestmove 33 index 39 score 2785594
+//! ```
+
+use crate::board::helpers::move_to_dani_move;
+use crate::board::rules::{Tide, MAX_WATER_LEVEL};
+use crate::board::searcher::{self, HashTable, Info, SearchPool};
+use crate::board::{board_from_str, GameState};
+use crate::cli::{validate_board, Options};
+use crate::output::{print_search_to, OutputFormat};
+
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Deepest search `go movetime` will attempt without an explicit depth
+pub const MAX_DEPTH: u8 = 12;
+
+struct RunningSearch {
+    handle: JoinHandle<()>,
+    stop: Arc<AtomicBool>,
+}
+
+pub struct Engine<W: Write + Send + 'static> {
+    out: Arc<Mutex<W>>,
+    hash_table: Arc<HashTable>,
+    /// Entries `hash_table` was sized for, what a saved table is capped at
+    table_entries: usize,
+    pool: SearchPool,
+    game: Option<GameState>,
+    depth: u8,
+    water: u8,
+    tide: Tide,
+    format: OutputFormat,
+    search: Option<RunningSearch>,
+}
+
+macro_rules! send {
+    ($out:expr, $($arg:tt)*) => {{
+        let mut out = $out.lock().unwrap();
+        let _ = writeln!(out, $($arg)*);
+        let _ = out.flush();
+    }};
+}
+
+impl<W: Write + Send + 'static> Engine<W> {
+    pub fn new(
+        out: W,
+        options: &Options,
+        hash_table: Arc<HashTable>,
+        pool: SearchPool,
+    ) -> Engine<W> {
+        Engine {
+            out: Arc::new(Mutex::new(out)),
+            hash_table,
+            table_entries: options.table_entries(),
+            pool,
+            game: None,
+            depth: options.depth,
+            water: options.water,
+            tide: options.tide,
+            format: options.format,
+            search: None,
+        }
+    }
+
+    /// Handles a single line of input, returns false once the engine should exit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        let command = match tokens.first() {
+            Some(command) => *command,
+            None => return true,
+        };
+
+        match command {
+            "bilge" => self.identify(),
+            "isready" => send!(self.out, "readyok"),
+            "setoption" => self.set_option(&tokens[1..]),
+            "newgame" => {
+                self.stop();
+                self.hash_table.clear();
+            }
+            "position" => self.position(&tokens[1..]),
+            "go" => self.go(&tokens[1..]),
+            "stop" => self.stop(),
+            "quit" => {
+                self.stop();
+                return false;
+            }
+            _ if validate_board(command).is_ok() => self.legacy_query(&tokens),
+            _ => send!(self.out, "info string unknown command {}", command),
+        }
+
+        true
+    }
+
+    /// Waits for any running search to finish by itself
+    pub fn wait(&mut self) {
+        if let Some(search) = self.search.take() {
+            let _ = search.handle.join();
+        }
+    }
+
+    fn identify(&self) {
+        send!(self.out, "id name bilgebot");
+        send!(self.out, "id author JackThomson2");
+        send!(
+            self.out,
+            "option name Depth type spin default {} min 1 max {}",
+            self.depth,
+            MAX_DEPTH
+        );
+        send!(
+            self.out,
+            "option name Water type spin default {} min 0 max {}",
+            self.water,
+            MAX_WATER_LEVEL
+        );
+        send!(
+            self.out,
+            "option name Rise type spin default {} min 0 max {}",
+            self.tide.rise,
+            MAX_WATER_LEVEL
+        );
+        send!(
+            self.out,
+            "option name Pump type spin default {} min 0",
+            self.tide.pump_score
+        );
+        send!(self.out, "option name Hash type spin min 1");
+        send!(
+            self.out,
+            "option name Threads type spin default {} min 1",
+            self.pool.threads()
+        );
+        send!(
+            self.out,
+            "option name ParallelDepth type spin default {} min 1 max {}",
+            self.pool.parallel_depth,
+            MAX_DEPTH
+        );
+        send!(self.out, "bilgeok");
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        // setoption name <name> value <value>
+        let (name, value) = match args {
+            ["name", name, "value", value] => (name.to_ascii_lowercase(), *value),
+            _ => {
+                send!(self.out, "info string usage setoption name <name> value <value>");
+                return;
+            }
+        };
+
+        let value: usize = match value.parse() {
+            Ok(value) => value,
+            Err(_) => {
+                send!(self.out, "info string {} expects a number", name);
+                return;
+            }
+        };
+
+        match name.as_str() {
+            "depth" if (1..=MAX_DEPTH as usize).contains(&value) => self.depth = value as u8,
+            "water" if value <= MAX_WATER_LEVEL as usize => {
+                self.water = value as u8;
+
+                if let Some(game) = self.game.as_mut() {
+                    game.water_level = self.water;
+                }
+            }
+            "rise" if value <= MAX_WATER_LEVEL as usize => self.set_tide(Tide {
+                rise: value as u8,
+                ..self.tide
+            }),
+            "pump" if value <= u16::MAX as usize => self.set_tide(Tide {
+                pump_score: value as u16,
+                ..self.tide
+            }),
+            "hash" if value >= 1 => {
+                self.stop();
+                self.table_entries = searcher::entries_for_mb(value);
+                self.hash_table = Arc::new(searcher::new_hash_table(self.table_entries));
+            }
+            "threads" if value >= 1 => {
+                self.stop();
+
+                match SearchPool::with_threads(value, self.pool.parallel_depth) {
+                    Ok(pool) => self.pool = pool,
+                    Err(err) => send!(self.out, "info string {}", err),
+                }
+            }
+            "paralleldepth" if (1..=MAX_DEPTH as usize).contains(&value) => {
+                self.stop();
+                self.pool.parallel_depth = value as u8;
+            }
+            "depth" | "water" | "rise" | "pump" | "hash" | "threads" | "paralleldepth" => {
+                send!(self.out, "info string {} out of range", name)
+            }
+            _ => send!(self.out, "info string unknown option {}", name),
+        }
+    }
+
+    fn set_tide(&mut self, tide: Tide) {
+        self.tide = tide;
+
+        if let Some(game) = self.game.as_mut() {
+            game.tide = tide;
+        }
+    }
+
+    fn position(&mut self, args: &[&str]) {
+        // position <board> [water <n>]
+        let water = match args {
+            [_] => Ok(self.water),
+            [_, "water", water] => match water.parse::<u8>() {
+                Ok(water) if water <= MAX_WATER_LEVEL => Ok(water),
+                _ => Err(format!("water has to be 0 to {}", MAX_WATER_LEVEL)),
+            },
+            _ => Err("usage position <board> [water <n>]".to_string()),
+        };
+
+        let result = water.and_then(|water| {
+            validate_board(args[0])?;
+            let mut game = board_from_str(args[0], water);
+            game.tide = self.tide;
+            Ok(game)
+        });
+
+        match result {
+            Ok(game) => self.game = Some(game),
+            Err(err) => send!(self.out, "info string {}", err),
+        }
+    }
+
+    fn go(&mut self, args: &[&str]) {
+        let game = match self.game {
+            Some(game) => game,
+            None => {
+                send!(self.out, "info string no position set");
+                return;
+            }
+        };
+
+        let mut depth = None;
+        let mut movetime = None;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let value = iter.next().and_then(|value| value.parse::<u64>().ok());
+
+            match (*arg, value) {
+                ("depth", Some(value)) if value >= 1 => depth = Some(value.min(MAX_DEPTH as u64) as u8),
+                ("movetime", Some(value)) => movetime = Some(Duration::from_millis(value)),
+                _ => {
+                    send!(self.out, "info string usage go [depth <n>] [movetime <ms>]");
+                    return;
+                }
+            }
+        }
+
+        let max_depth = match (depth, movetime) {
+            (Some(depth), _) => depth,
+            (None, Some(_)) => MAX_DEPTH,
+            (None, None) => self.depth,
+        };
+
+        self.stop();
+
+        let stop = Arc::new(AtomicBool::new(false));
+
+        if let Some(movetime) = movetime {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(movetime);
+                stop.store(true, Ordering::Relaxed);
+            });
+        }
+
+        searcher::trim_table(&self.hash_table, self.table_entries);
+
+        let out = self.out.clone();
+        let hash_table = self.hash_table.clone();
+        let pool = self.pool.clone();
+        let worker_stop = stop.clone();
+
+        let handle = thread::spawn(move || {
+            deepen(game, max_depth, &hash_table, &pool, &worker_stop, &out);
+        });
+
+        self.search = Some(RunningSearch { handle, stop });
+    }
+
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            let _ = search.handle.join();
+        }
+    }
+
+    /// The old `<board> <depth> <water>` query, answered straight away
+    fn legacy_query(&mut self, tokens: &[&str]) {
+        let parsed = match tokens {
+            [_, depth, water] => depth.parse::<u8>().ok().zip(water.parse::<u8>().ok()),
+            _ => None,
+        };
+
+        let (depth, water) = match parsed {
+            Some((_, water)) if water > MAX_WATER_LEVEL => {
+                send!(self.out, "info string water has to be 0 to {}", MAX_WATER_LEVEL);
+                return;
+            }
+            Some((depth, water)) if depth > 0 => (depth, water),
+            _ => {
+                send!(self.out, "Not enough parameters");
+                return;
+            }
+        };
+
+        self.stop();
+        searcher::trim_table(&self.hash_table, self.table_entries);
+
+        let now = Instant::now();
+        let mut game = board_from_str(tokens[0], water);
+        game.tide = self.tide;
+        let best_moves = searcher::find_best_move_list_in(&game, depth, false, &self.hash_table, &self.pool);
+
+        let mut out = self.out.lock().unwrap();
+        print_search_to(&mut *out, self.format, &best_moves, depth, water, now.elapsed());
+        let _ = out.flush();
+    }
+}
+
+/// Searches one depth at a time so a stop still leaves the last full result
+fn deepen<W: Write>(
+    game: GameState,
+    max_depth: u8,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+    stop: &AtomicBool,
+    out: &Mutex<W>,
+) {
+    let now = Instant::now();
+    let mut best: Option<Info> = None;
+
+    for depth in 1..=max_depth {
+        let list = searcher::find_best_move_list_with_stop(&game, depth, false, hash_table, stop, pool);
+
+        let list = match list {
+            Some(list) => list,
+            None => break,
+        };
+
+        let top = match list.turns.first() {
+            Some(top) => *top,
+            None => break,
+        };
+
+        send!(
+            out,
+            "info depth {} score {} move {} index {} hashfull {} time {}",
+            depth,
+            top.score,
+            move_to_dani_move(top.turn),
+            top.turn,
+            hash_table.len(),
+            now.elapsed().as_millis()
+        );
+
+        best = Some(top);
+    }
+
+    match best {
+        Some(best) => send!(
+            out,
+            "bestmove {} index {} score {}",
+            move_to_dani_move(best.turn),
+            best.turn,
+            best.score
+        ),
+        None => send!(out, "bestmove none"),
+    }
+}
+
+/// Runs the protocol until `quit` or the input closes, handing back the
+/// table the engine finished with and the entries it was sized for, both
+/// new when `setoption name Hash` replaced it
+pub fn run<R: BufRead, W: Write + Send + 'static>(
+    input: R,
+    out: W,
+    options: &Options,
+    hash_table: Arc<HashTable>,
+    pool: SearchPool,
+) -> (Arc<HashTable>, usize) {
+    let mut engine = Engine::new(out, options, hash_table, pool);
+
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        if !engine.handle(&line) {
+            break;
+        }
+    }
+
+    engine.wait();
+    (engine.hash_table.clone(), engine.table_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TEST_BOARD;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    fn engine() -> (Engine<Shared>, Shared) {
+        let out = Shared::default();
+        let engine = Engine::new(
+            out.clone(),
+            &Options::default(),
+            Arc::new(searcher::new_hash_table(1024)),
+            SearchPool::with_threads(2, 1).unwrap(),
+        );
+        (engine, out)
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (mut engine, out) = engine();
+
+        engine.handle("bilge");
+        engine.handle("isready");
+        engine.handle("setoption name Depth value 4");
+        engine.handle("setoption name Threads value 3");
+        engine.handle("setoption name Rise value 1");
+        engine.handle("setoption name Pump value 200");
+        engine.handle("setoption name Colour value 4");
+
+        let lines = out.lines();
+        assert_eq!(lines[0], "id name bilgebot");
+        assert!(lines.contains(&"bilgeok".to_string()));
+        assert!(lines.contains(&"readyok".to_string()));
+        assert_eq!(lines.last().unwrap(), "info string unknown option colour");
+        assert_eq!(engine.depth, 4);
+        assert_eq!(engine.pool.threads(), 3);
+        assert_eq!(engine.tide, Tide { rise: 1, pump_score: 200 });
+    }
+
+    #[test]
+    fn test_go_depth() {
+        let (mut engine, out) = engine();
+
+        engine.handle(&format!("position {} water 3", TEST_BOARD));
+        engine.handle("go depth 3");
+        engine.wait();
+
+        let lines = out.lines();
+        let infos = lines.iter().filter(|line| line.starts_with("info depth")).count();
+
+        assert_eq!(infos, 3);
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_stop_and_quit() {
+        let (mut engine, out) = engine();
+
+        engine.handle("go depth 3");
+        assert_eq!(out.lines().last().unwrap(), "info string no position set");
+
+        engine.handle(&format!("position {} water 12", TEST_BOARD));
+        assert_eq!(out.lines().last().unwrap(), "info string water has to be 0 to 11");
+        assert!(engine.game.is_none());
+
+        // The bare board form is held to the same limit
+        engine.handle(&format!("{} 3 12", TEST_BOARD));
+        assert_eq!(out.lines().last().unwrap(), "info string water has to be 0 to 11");
+
+        engine.handle(&format!("position {}", TEST_BOARD));
+        engine.handle(&format!("go depth {}", MAX_DEPTH));
+        engine.handle("stop");
+
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+        assert!(!engine.handle("quit"));
+    }
+
+    #[test]
+    fn test_run_hands_back_the_table() {
+        let options = Options::default();
+        let table = Arc::new(searcher::new_hash_table(1024));
+        let pool = SearchPool::with_threads(2, 1).unwrap();
+
+        let run_lines = |lines: &str| {
+            let input = format!("{}position {}\ngo depth 2\n", lines, TEST_BOARD);
+            run(input.as_bytes(), Shared::default(), &options, table.clone(), pool.clone())
+        };
+
+        let (kept, entries) = run_lines("");
+        assert!(Arc::ptr_eq(&kept, &table));
+        assert_eq!(entries, options.table_entries());
+
+        // A new size is a new table, the one searched into after it
+        let (replaced, entries) = run_lines("setoption name Hash value 1\n");
+        assert!(!Arc::ptr_eq(&replaced, &table));
+        assert!(!replaced.is_empty());
+        assert_eq!(entries, searcher::entries_for_mb(1));
+    }
+}
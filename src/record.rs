@@ -0,0 +1,358 @@
+//! Played games saved as JSON lines. The first line is a `RecordHeader`
+//! with the starting board and how the water moves, then one `RecordedTurn`
+//! per move: the move made, what it scored, the pieces that dropped into
+//! the gaps it left and what the engine suggested at the time. Being one
+//! line per move a game is written as it is played, see `RecordWriter`,
+//! so a game cut short keeps every move up to then.
+//!
+//! Replaying runs the moves again on the starting board, filling the gaps
+//! with the recorded pieces, so every position the player saw comes back
+//! exactly.
+
+use crate::board::defs::*;
+use crate::board::rules::{Tide, MAX_WATER_LEVEL};
+use crate::board::{board_from_str, GameState};
+use crate::cli::validate_board;
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Bumped whenever a record written by an older build can't be replayed
+pub const RECORD_VERSION: u32 = 1;
+
+/// Points a move may fall short of the best before replay calls it a mistake
+pub const MISTAKE_MARGIN: f32 = 1.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordHeader {
+    pub version: u32,
+    pub board: String,
+    pub water: u8,
+    #[serde(default)]
+    pub rise: u8,
+    #[serde(default)]
+    pub pump: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedTurn {
+    #[serde(rename = "move")]
+    pub turn: usize,
+    pub score: f32,
+    /// Pieces that filled the gaps after the move, lowest position first
+    pub refill: Vec<Pieces>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggested: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggested_score: Option<f32>,
+}
+
+/// The board before a recorded move, and the move
+#[derive(Clone)]
+pub struct Position<'a> {
+    /// Counting from 1
+    pub number: usize,
+    pub game: GameState,
+    pub turn: &'a RecordedTurn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    pub header: RecordHeader,
+    pub turns: Vec<RecordedTurn>,
+}
+
+/// The board as the 72 digits `solve` takes, `None` while it has gaps
+pub fn board_digits(game: &GameState) -> Option<String> {
+    if game.board.iter().any(|piece| *piece > 9) {
+        return None;
+    }
+
+    Some(game.board.iter().map(|piece| piece.to_string()).collect())
+}
+
+/// Positions waiting for a new piece, lowest first, the order `refill`
+/// lists them in
+pub fn gaps(game: &GameState) -> Vec<usize> {
+    (0..BOARD_SIZE).filter(|pos| game.board[*pos] == CLEARED).collect()
+}
+
+impl GameRecord {
+    /// An empty record starting from `game`, which can't have gaps
+    pub fn new(game: &GameState) -> Result<GameRecord, String> {
+        let board = board_digits(game).ok_or("Can't record a board with empty cells")?;
+
+        Ok(GameRecord {
+            header: RecordHeader {
+                version: RECORD_VERSION,
+                board,
+                water: game.water_level,
+                rise: game.tide.rise,
+                pump: game.tide.pump_score,
+            },
+            turns: Vec::new(),
+        })
+    }
+
+    pub fn start(&self) -> GameState {
+        let mut game = board_from_str(&self.header.board, self.header.water);
+        game.tide = Tide {
+            rise: self.header.rise,
+            pump_score: self.header.pump,
+        };
+        game
+    }
+
+    pub fn to_jsonl(&self) -> String {
+        let mut out = serde_json::to_string(&self.header).unwrap();
+        out.push('\n');
+
+        for turn in self.turns.iter() {
+            out.push_str(&serde_json::to_string(turn).unwrap());
+            out.push('\n');
+        }
+
+        out
+    }
+
+    pub fn from_jsonl(input: &str) -> Result<GameRecord, String> {
+        let mut lines = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let (_, first) = lines.next().ok_or("The record is empty")?;
+        let header: RecordHeader =
+            serde_json::from_str(first).map_err(|err| format!("Line 1: bad header: {}", err))?;
+
+        if header.version != RECORD_VERSION {
+            return Err(format!(
+                "Record is version {}, this build reads version {}",
+                header.version, RECORD_VERSION
+            ));
+        }
+
+        validate_board(&header.board).map_err(|err| format!("Line 1: {}", err))?;
+
+        if header.water > MAX_WATER_LEVEL || header.rise > MAX_WATER_LEVEL {
+            return Err(format!(
+                "Line 1: water and rise have to be 0 to {}",
+                MAX_WATER_LEVEL
+            ));
+        }
+
+        let turns = lines
+            .map(|(number, line)| {
+                let turn: RecordedTurn = serde_json::from_str(line)
+                    .map_err(|err| format!("Line {}: {}", number + 1, err))?;
+
+                // Anything past a jellyfish is an empty or missing cell
+                if let Some(bad) = turn.refill.iter().find(|piece| **piece > JELLYFISH) {
+                    return Err(format!("Line {}: {} isn't a piece", number + 1, bad));
+                }
+
+                Ok(turn)
+            })
+            .collect::<Result<Vec<RecordedTurn>, String>>()?;
+
+        Ok(GameRecord { header, turns })
+    }
+
+    /// Every position of the game in order, checking each move is legal,
+    /// scores what the record says and leaves as many gaps as it refills
+    pub fn positions(&self) -> Result<Vec<Position<'_>>, String> {
+        let mut game = self.start();
+        let mut positions = Vec::with_capacity(self.turns.len());
+
+        for (index, turn) in self.turns.iter().enumerate() {
+            let number = index + 1;
+            positions.push(Position { number, game, turn });
+
+            let swapped = game.swap(turn.turn).map_err(|reason| {
+                format!("Turn {}: can't play move {}, {}", number, turn.turn, reason)
+            })?;
+
+            if swapped.score != turn.score {
+                return Err(format!(
+                    "Turn {}: move {} scores {}, the record says {}",
+                    number, turn.turn, swapped.score, turn.score
+                ));
+            }
+
+            let gaps = gaps(&game);
+
+            if gaps.len() != turn.refill.len() {
+                return Err(format!(
+                    "Turn {}: the move leaves {} gaps, the record refills {}",
+                    number,
+                    gaps.len(),
+                    turn.refill.len()
+                ));
+            }
+
+            for (pos, piece) in gaps.into_iter().zip(turn.refill.iter()) {
+                game.board[pos] = *piece;
+            }
+
+            game.apply_tide(swapped.score);
+        }
+
+        Ok(positions)
+    }
+}
+
+/// Writes a record one line at a time, flushing each line as it goes
+pub struct RecordWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Starts the record with the header for `game`
+    pub fn new(out: W, game: &GameState) -> Result<RecordWriter<W>, String> {
+        let header = GameRecord::new(game)?.header;
+        let mut writer = RecordWriter { out };
+
+        writer.line(&header)?;
+        Ok(writer)
+    }
+
+    pub fn push(&mut self, turn: &RecordedTurn) -> Result<(), String> {
+        self.line(turn)
+    }
+
+    fn line<T: Serialize>(&mut self, value: &T) -> Result<(), String> {
+        let mut line = serde_json::to_string(value).unwrap();
+        line.push('\n');
+
+        self.out
+            .write_all(line.as_bytes())
+            .and_then(|_| self.out.flush())
+            .map_err(|err| format!("Could not write the record: {}", err))
+    }
+}
+
+#[cfg(all(test, not(feature = "small-board")))]
+mod tests {
+    use super::*;
+    use crate::board::generate_settled_board;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Plays the first move that scores every turn, refilling from `rng`
+    fn play(game: &GameState, turns: usize, rng: &mut StdRng) -> (GameRecord, Vec<GameState>) {
+        let mut record = GameRecord::new(game).unwrap();
+        let mut game = *game;
+        let mut seen = Vec::new();
+
+        for _ in 0..turns {
+            let moves = game.get_moves();
+            let turn = *moves
+                .iter()
+                .find(|pos| {
+                    let mut copy = game;
+                    copy.swap(**pos).unwrap().score > 0.0
+                })
+                .unwrap_or(&moves[0]);
+
+            seen.push(game);
+            let score = game.swap(turn).unwrap().score;
+
+            let refill = gaps(&game)
+                .into_iter()
+                .map(|pos| {
+                    game.board[pos] = rng.gen_range(0..6);
+                    game.board[pos]
+                })
+                .collect();
+
+            game.apply_tide(score);
+            record.turns.push(RecordedTurn {
+                turn,
+                score,
+                refill,
+                suggested: None,
+                suggested_score: None,
+            });
+        }
+
+        (record, seen)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = StdRng::seed_from_u64(47);
+        let mut game = generate_settled_board(&mut rng, 4);
+        game.tide = Tide {
+            rise: 1,
+            pump_score: 20,
+        };
+
+        let (mut record, seen) = play(&game, 8, &mut rng);
+        record.turns[0].suggested = Some(12);
+        record.turns[0].suggested_score = Some(30.5);
+
+        let read = GameRecord::from_jsonl(&record.to_jsonl()).unwrap();
+        assert_eq!(read, record);
+        assert_eq!(read.start().tide, game.tide);
+
+        // Written a move at a time it comes out the same
+        let mut out = Vec::new();
+        let mut writer = RecordWriter::new(&mut out, &game).unwrap();
+        for turn in record.turns.iter() {
+            writer.push(turn).unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), record.to_jsonl());
+
+        let positions = read.positions().unwrap();
+        assert_eq!(positions.len(), 8);
+
+        for (position, expected) in positions.iter().zip(seen.iter()) {
+            assert_eq!(position.game.board, expected.board);
+            assert_eq!(position.game.water_level, expected.water_level);
+        }
+    }
+
+    #[test]
+    fn test_bad_records() {
+        let mut rng = StdRng::seed_from_u64(48);
+        let game = generate_settled_board(&mut rng, 3);
+        let (record, _) = play(&game, 3, &mut rng);
+
+        let mut wrong_score = record.clone();
+        wrong_score.turns[1].score += 1.0;
+        assert!(wrong_score.positions().is_err());
+
+        let mut short_refill = record.clone();
+        short_refill.turns[0].refill.pop();
+        assert!(short_refill.positions().is_err());
+
+        let mut illegal = record.clone();
+        illegal.turns[2].turn = 5;
+        assert!(illegal.positions().is_err());
+
+        let old = record.to_jsonl().replacen("\"version\":1", "\"version\":0", 1);
+        assert!(GameRecord::from_jsonl(&old).is_err());
+        assert!(GameRecord::from_jsonl("").is_err());
+
+        let drowned = record.to_jsonl().replacen("\"water\":3", "\"water\":12", 1);
+        assert_eq!(
+            GameRecord::from_jsonl(&drowned),
+            Err("Line 1: water and rise have to be 0 to 11".to_string())
+        );
+
+        let refilled = record.turns.iter().position(|turn| !turn.refill.is_empty()).unwrap();
+
+        for bad in [CLEARED, NULL, 200].iter() {
+            let mut bad_refill = record.clone();
+            bad_refill.turns[refilled].refill[0] = *bad;
+            assert_eq!(
+                GameRecord::from_jsonl(&bad_refill.to_jsonl()),
+                Err(format!("Line {}: {} isn't a piece", refilled + 2, bad))
+            );
+        }
+
+        let mut gappy = game;
+        gappy.board[71] = CLEARED;
+        assert!(GameRecord::new(&gappy).is_err());
+    }
+}
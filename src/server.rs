@@ -0,0 +1,417 @@
+//! Small HTTP/JSON server so tools can query one long running engine
+//! instead of paying for process start and table allocation per query.
+//!
+//! ```text
+//! POST /solve  {"board": "3424...3456", "depth": 6, "water": 3}
+//! GET  /health
+//! ```
+//!
+//! `/solve` answers with the same object as `--format json`. Every
+//! connection gets its own thread, up to `MAX_CONNECTIONS` at once, and all
+//! of them share one table which is emptied before a search once it has
+//! grown past `--hash-mb`.
+
+use crate::board::board_from_str;
+use crate::board::rules::{Tide, MAX_WATER_LEVEL};
+use crate::board::searcher::{self, HashTable, SearchPool};
+use crate::cli::{validate_board, Options};
+use crate::output::SearchReport;
+use crate::protocol::MAX_DEPTH;
+
+use serde::Deserialize;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bodies above this are refused, a board request is well under 1KB
+const MAX_BODY: usize = 16 * 1024;
+
+/// Connections answered at once, any more are turned away with a 503
+const MAX_CONNECTIONS: usize = 32;
+
+/// How long a client gets to send its request before it's dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct SolveRequest {
+    board: String,
+    depth: Option<u8>,
+    water: Option<u8>,
+}
+
+/// What a request gets when it doesn't say, taken from the command line
+#[derive(Copy, Clone)]
+struct Defaults {
+    depth: u8,
+    water: u8,
+    tide: Tide,
+    /// The table is emptied before a search once it holds more
+    max_entries: usize,
+}
+
+/// Counts a connection as open until it's dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Response {
+        Response {
+            status: "200 OK",
+            body,
+        }
+    }
+
+    fn error(status: &'static str, message: &str) -> Response {
+        Response {
+            status,
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    hash_table: Arc<HashTable>,
+    pool: SearchPool,
+    defaults: Defaults,
+    open: Arc<AtomicUsize>,
+    max_connections: usize,
+    read_timeout: Duration,
+}
+
+impl Server {
+    pub fn bind(
+        port: u16,
+        options: &Options,
+        hash_table: Arc<HashTable>,
+        pool: SearchPool,
+    ) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(("127.0.0.1", port))?,
+            hash_table,
+            pool,
+            defaults: Defaults {
+                depth: options.depth,
+                water: options.water,
+                tide: options.tide,
+                max_entries: options.table_entries(),
+            },
+            open: Arc::new(AtomicUsize::new(0)),
+            max_connections: MAX_CONNECTIONS,
+            read_timeout: READ_TIMEOUT,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections forever, each one is answered on its own thread
+    /// while there are fewer than `MAX_CONNECTIONS` open
+    pub fn run(&self) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            let open = self.open.fetch_add(1, Ordering::Relaxed);
+            let slot = Slot(self.open.clone());
+
+            if open >= self.max_connections {
+                let busy = Response::error("503 Service Unavailable", "too many connections");
+                let _ = write_response(stream, &busy);
+                continue;
+            }
+
+            let hash_table = self.hash_table.clone();
+            let pool = self.pool.clone();
+            let defaults = self.defaults;
+            let read_timeout = self.read_timeout;
+
+            thread::spawn(move || {
+                let _slot = slot;
+                let _ = handle_connection(stream, read_timeout, &hash_table, &pool, defaults);
+            });
+        }
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    read_timeout: Duration,
+    hash_table: &HashTable,
+    pool: &SearchPool,
+    defaults: Defaults,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(read_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let response = match read_request(&mut reader) {
+        Ok((method, path, body)) => route(&method, &path, &body, hash_table, pool, defaults),
+        Err(message) => Response::error("400 Bad Request", &message),
+    };
+
+    write_response(stream, &response)
+}
+
+fn write_response(mut stream: TcpStream, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// Reads the request line, headers and body, returning the method, path and body
+fn read_request<R: BufRead>(reader: &mut R) -> Result<(String, String, Vec<u8>), String> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|_| "could not read request")?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or("missing method")?.to_string();
+    let path = parts.next().ok_or("missing path")?.to_string();
+
+    let mut content_length = 0;
+
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|_| "could not read headers")?;
+
+        let header = line.trim_end();
+        if read == 0 || header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| "bad content length")?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err("body too large".to_string());
+    }
+
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| "body shorter than content length")?;
+
+    Ok((method, path, body))
+}
+
+fn route(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    hash_table: &HashTable,
+    pool: &SearchPool,
+    defaults: Defaults,
+) -> Response {
+    match (method, path) {
+        ("GET", "/health") => Response::ok(
+            serde_json::json!({ "status": "ok", "table_entries": hash_table.len() }).to_string(),
+        ),
+        ("POST", "/solve") => solve(body, hash_table, pool, defaults),
+        (_, "/health") | (_, "/solve") => Response::error("405 Method Not Allowed", "wrong method"),
+        _ => Response::error("404 Not Found", "unknown path"),
+    }
+}
+
+fn solve(
+    body: &[u8],
+    hash_table: &HashTable,
+    pool: &SearchPool,
+    defaults: Defaults,
+) -> Response {
+    let request: SolveRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => return Response::error("400 Bad Request", &err.to_string()),
+    };
+
+    if let Err(err) = validate_board(&request.board) {
+        return Response::error("400 Bad Request", &err);
+    }
+
+    let depth = request.depth.unwrap_or(defaults.depth);
+    let water = request.water.unwrap_or(defaults.water);
+
+    if depth == 0 || depth > MAX_DEPTH {
+        return Response::error("400 Bad Request", "depth out of range");
+    }
+
+    if water > MAX_WATER_LEVEL {
+        return Response::error("400 Bad Request", "water out of range");
+    }
+
+    searcher::trim_table(hash_table, defaults.max_entries);
+
+    let now = Instant::now();
+    let mut game = board_from_str(&request.board, water);
+    game.tide = defaults.tide;
+    let best_moves = searcher::find_best_move_list_in(&game, depth, false, hash_table, pool);
+
+    Response::ok(SearchReport::new(&best_moves, depth, water, now.elapsed()).to_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::book::Book;
+    use crate::config::TEST_BOARD;
+    use std::io::Read;
+
+    fn bind() -> Server {
+        Server::bind(
+            0,
+            &Options::default(),
+            Arc::new(searcher::new_hash_table(1024)),
+            SearchPool::default(),
+        )
+        .unwrap()
+    }
+
+    fn run(server: Server) -> SocketAddr {
+        let addr = server.local_addr().unwrap();
+
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    fn start() -> SocketAddr {
+        run(bind())
+    }
+
+    fn request(addr: SocketAddr, raw: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn post(addr: SocketAddr, body: &str) -> String {
+        request(
+            addr,
+            &format!(
+                "POST /solve HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        )
+    }
+
+    #[test]
+    fn test_solve() {
+        let addr = start();
+        let body = format!("{{\"board\": \"{}\", \"depth\": 3}}", TEST_BOARD);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let body = body.clone();
+                thread::spawn(move || post(addr, &body))
+            })
+            .collect();
+
+        for handle in handles {
+            let response = handle.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+            let json = response.split("\r\n\r\n").nth(1).unwrap();
+            let parsed: serde_json::Value = serde_json::from_str(json).unwrap();
+            assert_eq!(parsed["depth"], 3);
+            assert!(parsed["best"]["move"].is_number());
+        }
+
+        let health = request(addr, "GET /health HTTP/1.1\r\n\r\n");
+        assert!(health.starts_with("HTTP/1.1 200 OK"));
+        assert!(!health.contains("\"table_entries\":0"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let addr = start();
+
+        assert!(post(addr, "{\"board\": \"1234\"}").starts_with("HTTP/1.1 400"));
+        assert!(post(addr, "not json").starts_with("HTTP/1.1 400"));
+
+        let drowned = format!("{{\"board\": \"{}\", \"water\": 200}}", TEST_BOARD);
+        assert!(post(addr, &drowned).starts_with("HTTP/1.1 400"));
+        assert!(request(addr, "GET /solve HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(request(addr, "GET /nowhere HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_solve_from_book() {
+        let game = board_from_str(TEST_BOARD, 3);
+        let hash_table = Arc::new(searcher::new_hash_table(1024));
+
+        let mut ranked = searcher::find_best_move_list(&game, 3, false, &hash_table);
+        ranked.turns.iter_mut().for_each(|info| info.score += 1000.0);
+
+        let mut book = Book::new();
+        book.insert(&game, &ranked, 3);
+
+        let mut pool = SearchPool::default();
+        pool.book = Some(Arc::new(book));
+        let addr = run(Server::bind(0, &Options::default(), hash_table, pool).unwrap());
+
+        let body = format!("{{\"board\": \"{}\", \"depth\": 3, \"water\": 3}}", TEST_BOARD);
+        let response = post(addr, &body);
+        let json = response.split("\r\n\r\n").nth(1).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(json).unwrap();
+
+        // Answered from the book, still with every legal move ranked
+        assert_eq!(parsed["stats"]["positions"], 0);
+        assert_eq!(parsed["alternatives"].as_array().unwrap().len(), game.get_moves().len());
+        assert_eq!(parsed["best"]["score"].as_f64().unwrap() as f32, ranked.turns[0].score);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut server = bind();
+        server.max_connections = 0;
+        let full = run(server);
+
+        // Turned away before the request is read, so send nothing to read
+        let mut response = String::new();
+        TcpStream::connect(full).unwrap().read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"));
+
+        let mut server = bind();
+        server.read_timeout = Duration::from_millis(100);
+        let slow = run(server);
+
+        // A client which stops partway through its headers is dropped with a 400
+        let started = Instant::now();
+        let response = request(slow, "POST /solve HTTP/1.1\r\nContent-Length: 10\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}